@@ -0,0 +1,55 @@
+extern crate ngen_nsl;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use ngen_nsl::*;
+
+const COMMANDS: usize = 500;
+
+/// Builds a 500 command script mixing the command sizes found in real scripts:
+/// a generator prologue, then loops full of conditionals, single-operand commands and arithmetic
+fn representative_script() -> NSLScript {
+    let mut script = NSLScript::new();
+    script.add_commands(vec![
+        Commands::Set(memory_buffer(0), params(0)),
+        Commands::Divide(memory_buffer(0), constant(3)),
+        Commands::GenerateEuclidean(memory_buffer(0), memory_buffer(1)),
+    ]);
+    let mut step = 0;
+    while script.commands.len() + 8 < COMMANDS {
+        script.add_commands(vec![
+            Commands::LoopSet(constant(32)),
+            Commands::CondGT(step_velocity(step), constant(0)),
+            Commands::Set(step_pitch(step), random_note(40)),
+            Commands::Add(step_pitch(step), constant(48)),
+            Commands::CondEnd,
+            Commands::SelectTrack(constant(step % 4)),
+            Commands::LoopEnd,
+        ]);
+        step = (step + 1) % 32;
+    }
+    while script.commands.len() + 1 < COMMANDS {
+        script.add_command(Commands::ClearTrack);
+    }
+    script.add_command(Commands::End);
+    script
+}
+
+fn codec(c: &mut Criterion) {
+    let script = representative_script();
+    assert_eq!(script.commands.len(), COMMANDS);
+    let data = script.code();
+
+    let mut group = c.benchmark_group("codec");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("encode", |b| b.iter(|| black_box(&script).code()));
+    group.bench_function("encode_into", |b| {
+        let mut out = Vec::new();
+        b.iter(|| black_box(&script).encode_into(&mut out))
+    });
+    group.bench_function("decode", |b| b.iter(|| NSLScript::try_from_u8_vec(black_box(&data)).unwrap()));
+    group.bench_function("cursor", |b| b.iter(|| CommandCursor::new(black_box(&data)).unwrap().count()));
+    group.finish();
+}
+
+criterion_group!(benches, codec);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+extern crate ngen_nsl;
+
+use std::process::ExitCode;
+use ngen_nsl::*;
+
+const USAGE: &str = "Usage:
+    nslc assemble <input.asm> -o <output.nsl>
+    nslc disassemble <input.nsl>
+    nslc info <input.nsl>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    let result = match args.as_slice() {
+        ["assemble", input, "-o", output] => assemble_file(input, output),
+        ["disassemble", input] => load(input).map(|script| print!("{}", asm::disassemble(&script))),
+        ["info", input] => load(input).map(|script| info(&script)),
+        _ => Err(String::from(USAGE))
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Assembles a text script and writes the encoded script to `output`
+fn assemble_file(input: &str, output: &str) -> Result<(), String> {
+    let src = std::fs::read_to_string(input).map_err(|error| format!("{}: {}", input, error))?;
+    let script = asm::assemble(&src).map_err(|error| format!("{}: {}", input, error))?;
+    script.export_hex(output).map_err(|error| format!("{}: {}", output, error))?;
+    println!("Wrote {} bytes to {}", script.len_bytes(), output);
+    Ok(())
+}
+
+/// Reads an encoded script
+fn load(path: &str) -> Result<NSLScript, String> {
+    let data = NSLScript::import_hex_as_vec(path).map_err(|error| format!("{}: {}", path, error))?;
+    NSLScript::try_from_u8_vec(&data).map_err(|error| format!("{}: {}", path, error))
+}
+
+/// Prints the listing of the script followed by the validation results and warnings
+fn info(script: &NSLScript) {
+    println!("{} commands, {} bytes", script.commands.len(), script.len_bytes());
+    print!("{}", script.listing());
+    match script.validate() {
+        Ok(()) => println!("No problems found"),
+        Err(errors) => {
+            for error in errors {
+                println!("Command {}: {:?}", error.index(), error);
+            }
+        }
+    }
+    for warning in script.validate_warnings() {
+        println!("Warning, command {}: {:?}", warning.index(), warning);
+    }
+}
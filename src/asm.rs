@@ -0,0 +1,342 @@
+/*
+
+Text assembler / disassembler for NSL scripts
+
+Scripts are written one command per line as a mnemonic followed by comma separated operands:
+
+    start:
+    SET step_pitch[0], const 36     ; set the pitch of the first step
+    COND_GT params[0], const 64
+    JMP 0x0004
+    JMP start
+
+Data sources are written as `const N`, `random N`, `random_note N` or `name[N]`
+(`step_pitch`, `step_velocity`, `step_length`, `step_density`, `mem`, `params`, `scale`, `full_scale`).
+Values can be decimal or `0x` hex, and a value prefixed with `@` reads from the memory buffer (`step_pitch[@0]`).
+Lines ending with `:` define labels that `JMP` can target by name (see `NSLScript::resolve_labels`),
+and `JMP +N` / `JMP -N` jump relative to the start of the JMP (see `NSLScript::resolve_relative`).
+
+*/
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use log::debug;
+use crate::{Commands, DataSource, DataSourceKind, DataValue, NSLScript};
+
+/// Operand names / DataSource opcode pairs. Indexed sources are written as `name[N]`, the others as `name N`
+const SOURCES: &[(&str, u8, bool)] = &[
+    ("const", 0x00, false),
+    ("random", 0x01, false),
+    ("step_pitch", 0x02, true),
+    ("step_velocity", 0x03, true),
+    ("step_length", 0x04, true),
+    ("step_density", 0x05, true),
+    ("mem", 0x06, true),
+    ("params", 0x07, true),
+    ("scale", 0x08, true),
+    ("full_scale", 0x09, true),
+    ("random_note", 0x0A, false),
+];
+
+/// Errors reported by the assembler. Line numbers start at 1.
+#[derive(Debug)]
+pub enum AsmError {
+    /// The mnemonic doesn't match any command
+    UnknownMnemonic { line: usize, mnemonic: String },
+    /// The operand couldn't be parsed as a data source or address
+    BadOperand { line: usize, operand: String },
+    /// The command was given the wrong number of operands
+    OperandCount { line: usize, expected: usize, found: usize },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic { line, mnemonic } => write!(f, "line {}: unknown mnemonic \"{}\"", line, mnemonic),
+            AsmError::BadOperand { line, operand } => write!(f, "line {}: invalid operand \"{}\"", line, operand),
+            AsmError::OperandCount { line, expected, found } => write!(f, "line {}: expected {} operands, found {}", line, expected, found),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AsmError {}
+
+/// Parses a value (`36`, `0x24` or `@3` for a memory buffer reference).
+/// Only the byte range is checked here, see `parse_source` for the range of the source.
+fn parse_value(text: &str) -> Option<DataValue> {
+    let (buffer, number) = match text.strip_prefix('@') {
+        Some(rest) => (true, rest.trim()),
+        None => (false, text)
+    };
+    let value = u8::try_from(parse_number(number)?).ok()?;
+    Some(if buffer { DataValue::Buffer(value) } else { DataValue::Number(value) })
+}
+
+/// Parses a decimal or `0x` prefixed hex number
+fn parse_number(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok()
+    }
+}
+
+/// Returns true if the text can be a label name: a letter or `_` followed by letters, digits or `_`
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Parses a data source operand into its encoded bytes, rejecting values outside the range of the source
+fn parse_source(text: &str) -> Option<Vec<u8>> {
+    let (name, value, indexed) = match text.find('[') {
+        Some(open) => {
+            let value = text[open + 1..].strip_suffix(']')?;
+            (text[..open].trim(), value.trim(), true)
+        },
+        None => {
+            let (name, value) = text.split_once(char::is_whitespace)?;
+            (name, value.trim(), false)
+        }
+    };
+    let (_, opcode, _) = SOURCES.iter().find(|(n, _, i)| *n == name && *i == indexed)?;
+    let kind = DataSourceKind::try_from(*opcode).ok()?;
+    let source = DataSource::new(kind, parse_value(value)?);
+    if source.would_clip() {
+        return None;
+    }
+    Some(source.code())
+}
+
+/// Formats a data source operand, clipped to its valid range so it reassembles to valid bytes.
+/// Sets `clipped` if the value had to be changed.
+fn format_source(source: &DataSource, clipped: &mut bool) -> String {
+    if source.would_clip() {
+        *clipped = true;
+        let mut source = source.clone();
+        source.validate();
+        return source.to_string();
+    }
+    source.to_string()
+}
+
+/// Assembles a text script into a NSLScript
+pub fn assemble(src: &str) -> Result<NSLScript, AsmError> {
+    let mut script = NSLScript::new();
+    for (index, raw_line) in src.lines().enumerate() {
+        let line = index + 1;
+        let text = match raw_line.find(';') {
+            Some(comment) => &raw_line[..comment],
+            None => raw_line
+        }.trim();
+        if text.is_empty() {
+            continue;
+        }
+        if let Some(label) = text.strip_suffix(':') {
+            script.add_label(label.trim());
+            continue;
+        }
+
+        let (mnemonic, rest) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, rest)) => (mnemonic, rest.trim()),
+            None => (text, "")
+        };
+        // NOP and LABEL have no encoding, labels are written as `name:` instead
+        let template = match Commands::from_mnemonic(mnemonic) {
+            Some(Commands::None | Commands::Label(_)) | None => {
+                return Err(AsmError::UnknownMnemonic { line, mnemonic: mnemonic.to_string() })
+            },
+            Some(template) => template
+        };
+        let operands: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(|op| op.trim()).collect()
+        };
+
+        // Every operand takes 2 bytes after the opcode
+        let expected = (template.len() - 1) / 2;
+        if operands.len() != expected {
+            return Err(AsmError::OperandCount { line, expected, found: operands.len() });
+        }
+
+        if let (Commands::Jump(_), [operand]) = (&template, operands.as_slice()) {
+            if operand.starts_with(['+', '-']) {
+                match operand.parse::<i16>() {
+                    Ok(offset) => script.add_command(Commands::JumpRel(offset)),
+                    Err(_) => return Err(AsmError::BadOperand { line, operand: operand.to_string() })
+                }
+                continue;
+            }
+            if is_identifier(operand) {
+                script.add_command(Commands::JumpLabel(operand.to_string()));
+                continue;
+            }
+        }
+
+        let mut code = vec![template.opcode()];
+        for operand in operands {
+            let bytes = if let Commands::Jump(_) = template {
+                parse_number(operand).map(|address| address.to_be_bytes().to_vec())
+            } else {
+                parse_source(operand)
+            };
+            match bytes {
+                Some(bytes) => code.extend(bytes),
+                None => return Err(AsmError::BadOperand { line, operand: operand.to_string() })
+            }
+        }
+        let cmd = Commands::from_u8_vec(code);
+        debug!("Assembled line {}: {:?}", line, cmd);
+        script.add_command(cmd);
+    }
+    Ok(script)
+}
+
+/// Disassembles a NSLScript into text that can be assembled back into the same script.
+/// Values outside the range of their data source can't be assembled, they are clipped and the line
+/// is marked with a `; clipped` comment.
+pub fn disassemble(script: &NSLScript) -> String {
+    let mut text = String::new();
+    for command in &script.commands {
+        match command {
+            Commands::Label(name) => {
+                text.push_str(&format!("{}:\n", name));
+                continue;
+            },
+            Commands::JumpLabel(name) => {
+                text.push_str(&format!("JMP {}\n", name));
+                continue;
+            },
+            Commands::JumpRel(x) => {
+                text.push_str(&format!("JMP {:+}\n", x));
+                continue;
+            },
+            _ => {}
+        }
+        if let Commands::None = command {
+            text.push_str("; unknown command\n");
+            continue;
+        }
+        let mnemonic = command.mnemonic();
+        let mut clipped = false;
+        let operands: Vec<String> = if let Commands::Jump(x) = command {
+            vec![format!("0x{:04X}", x.get_value())]
+        } else if let Some((x, y)) = command.operands() {
+            vec![format_source(x, &mut clipped), format_source(y, &mut clipped)]
+        } else if let Some(x) = command.single_operand() {
+            vec![format_source(x, &mut clipped)]
+        } else {
+            Vec::new()
+        };
+        text.push_str(mnemonic);
+        if !operands.is_empty() {
+            text.push(' ');
+            text.push_str(&operands.join(", "));
+        }
+        if clipped {
+            text.push_str(&format!("   ; clipped from {}", command));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn assemble_test() {
+        let src = "
+            ; hello.rs as text
+            SET step_pitch[0], const 36
+            set step_velocity[0], const 0x64   ; mnemonics are case insensitive
+            COND_GT params[0], const 64
+            ADD step_pitch[@0], scale[@1]
+            COND_END
+            JMP 0x0120
+            END
+        ";
+        let script = assemble(src).unwrap();
+        assert_eq!(script.code(), vec![
+            0x4E, 0x53, 0x4C, 0x01,
+            0xA1, 0x02, 0x00, 0x00, 0x24,
+            0xA1, 0x03, 0x00, 0x00, 0x64,
+            0xD2, 0x07, 0x00, 0x00, 0x40,
+            0xB0, 0x02, 0x80, 0x08, 0x81,
+            0xD6,
+            0xC2, 0x01, 0x20,
+            0xFF
+        ]);
+    }
+
+    #[test]
+    fn assemble_errors() {
+        assert!(matches!(assemble("NOPE"), Err(AsmError::UnknownMnemonic { line: 1, .. })));
+        assert!(matches!(assemble("END\nSET mem[0]"), Err(AsmError::OperandCount { line: 2, expected: 2, found: 1 })));
+        assert!(matches!(assemble("LOOP velocity[0]"), Err(AsmError::BadOperand { line: 1, .. })));
+        assert!(matches!(assemble("LOOP const 200"), Err(AsmError::BadOperand { line: 1, .. })));
+        assert!(matches!(assemble("LOOP mem[@32]"), Err(AsmError::BadOperand { line: 1, .. })));
+        // Each source has its own range
+        assert!(matches!(assemble("LOOP params[5]"), Err(AsmError::BadOperand { line: 1, .. })));
+        assert!(matches!(assemble("LOOP step_pitch[32]"), Err(AsmError::BadOperand { line: 1, .. })));
+        assert!(matches!(assemble("LOOP const 300"), Err(AsmError::BadOperand { line: 1, .. })));
+        assert!(assemble("LOOP params[3]\nLOOP const 127\nLOOP step_pitch[@31]").is_ok());
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(assemble("NOPE").unwrap_err().to_string(), "line 1: unknown mnemonic \"NOPE\"");
+        assert_eq!(assemble("\nLOOP velocity[0]").unwrap_err().to_string(), "line 2: invalid operand \"velocity[0]\"");
+        assert_eq!(assemble("SET mem[0]").unwrap_err().to_string(), "line 1: expected 2 operands, found 1");
+        #[cfg(feature = "std")]
+        {
+            let error: Box<dyn std::error::Error> = Box::new(assemble("NOPE").unwrap_err());
+            assert!(error.source().is_none());
+        }
+    }
+
+    #[test]
+    fn assemble_labels() {
+        let mut script = assemble("start:\nADD mem[0], const 1\nJMP start\n").unwrap();
+        assert_eq!(disassemble(&script), "start:\nADD mem[0], const 1\nJMP start\n");
+
+        script.resolve_labels().unwrap();
+        assert_eq!(disassemble(&script), "ADD mem[0], const 1\nJMP 0x0004\n");
+
+        let mut script = assemble("ADD mem[0], const 1\nJMP -5\n").unwrap();
+        assert_eq!(script.commands[1], Commands::JumpRel(-5));
+        assert_eq!(disassemble(&script), "ADD mem[0], const 1\nJMP -5\n");
+        script.resolve_relative().unwrap();
+        assert_eq!(disassemble(&script), "ADD mem[0], const 1\nJMP 0x0004\n");
+        assert!(matches!(assemble("JMP -40000"), Err(AsmError::BadOperand { line: 1, .. })));
+        // Numbers that don't fit an address aren't label names
+        assert!(matches!(assemble("JMP 70000"), Err(AsmError::BadOperand { line: 1, .. })));
+        assert!(matches!(assemble("JMP 0xZZ"), Err(AsmError::BadOperand { line: 1, .. })));
+        assert_eq!(assemble("JMP _loop2").unwrap().commands[0], Commands::JumpLabel(String::from("_loop2")));
+    }
+
+    #[test]
+    fn disassemble_roundtrip() {
+        let script = NSLScript::try_from_u8_vec(FIXTURE).unwrap();
+        let text = disassemble(&script);
+        assert!(text.starts_with("SET mem[0], params[0]\nDIV mem[0], const 3\n"));
+
+        let reassembled = assemble(&text).unwrap();
+        assert_eq!(reassembled.code(), FIXTURE);
+    }
+
+    #[test]
+    fn disassemble_clipped() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::LoopSet(params(5)), Commands::Set(step_pitch(0), constant(36))]);
+        let text = disassemble(&script);
+        assert_eq!(text, "LOOP params[3]   ; clipped from LOOP params[5]\nSET step_pitch[0], const 36\n");
+        assert_eq!(assemble(&text).unwrap().commands[0], Commands::LoopSet(params(3)));
+    }
+}
@@ -0,0 +1,153 @@
+/*
+
+Structured view of NSL scripts
+
+Conditionals and loops are flat sequences in the binary format (`CondGT … CondEnd`, `LoopSet … LoopEnd`).
+`to_ast` nests them into blocks so they can be edited as a tree, and `from_ast` flattens them back.
+
+*/
+
+use alloc::vec::Vec;
+use crate::{Commands, DataSource, NSLScript};
+
+/// A statement of a structured script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt {
+    /// A conditional (one of the `Cond*` commands, without its CondEnd) and the statements it guards
+    If { cond: Commands, body: Vec<Stmt> },
+    /// A loop repeating its body `count` times
+    Loop { count: DataSource, body: Vec<Stmt> },
+    /// Any other command
+    Op(Commands),
+}
+
+/// Errors reported by `to_ast`. `index` is the index of the offending command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AstError {
+    /// The CondEnd at `index` doesn't close an open conditional
+    UnmatchedCondEnd { index: usize },
+    /// The LoopEnd at `index` doesn't close an open loop
+    UnmatchedLoopEnd { index: usize },
+    /// The conditional at `index` is never closed by a CondEnd
+    UnclosedCond { index: usize },
+    /// The LoopSet at `index` is never closed by a LoopEnd
+    UnclosedLoop { index: usize },
+}
+
+/// A block that is still open while building the tree
+struct Frame {
+    header: Commands,
+    index: usize,
+    body: Vec<Stmt>,
+}
+
+/// Nests the conditionals and loops of a script into blocks
+pub fn to_ast(script: &NSLScript) -> Result<Vec<Stmt>, AstError> {
+    let mut open: Vec<Frame> = Vec::new();
+    let mut top: Vec<Stmt> = Vec::new();
+
+    for (index, command) in script.commands.iter().enumerate() {
+        let stmt = match command {
+            Commands::LoopSet(_) => {
+                open.push(Frame { header: command.clone(), index, body: Vec::new() });
+                continue;
+            },
+            Commands::CondEnd => match open.pop() {
+                Some(frame) if frame.header.is_conditional() => Stmt::If { cond: frame.header, body: frame.body },
+                _ => return Err(AstError::UnmatchedCondEnd { index })
+            },
+            Commands::LoopEnd => match open.pop() {
+                Some(Frame { header: Commands::LoopSet(count), body, .. }) => Stmt::Loop { count, body },
+                _ => return Err(AstError::UnmatchedLoopEnd { index })
+            },
+            _ if command.is_conditional() => {
+                open.push(Frame { header: command.clone(), index, body: Vec::new() });
+                continue;
+            },
+            _ => Stmt::Op(command.clone())
+        };
+        match open.last_mut() {
+            Some(frame) => frame.body.push(stmt),
+            None => top.push(stmt)
+        }
+    }
+
+    match open.pop() {
+        Some(Frame { header: Commands::LoopSet(_), index, .. }) => Err(AstError::UnclosedLoop { index }),
+        Some(frame) => Err(AstError::UnclosedCond { index: frame.index }),
+        None => Ok(top)
+    }
+}
+
+/// Flattens structured statements back into a script
+pub fn from_ast(stmts: &[Stmt]) -> NSLScript {
+    let mut script = NSLScript::new();
+    flatten(stmts, &mut script);
+    script
+}
+
+fn flatten(stmts: &[Stmt], script: &mut NSLScript) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::If { cond, body } => {
+                script.add_command(cond.clone());
+                flatten(body, script);
+                script.add_command(Commands::CondEnd);
+            },
+            Stmt::Loop { count, body } => {
+                script.add_command(Commands::LoopSet(count.clone()));
+                flatten(body, script);
+                script.add_command(Commands::LoopEnd);
+            },
+            Stmt::Op(command) => script.add_command(command.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn nested_blocks() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(memory_buffer(0), constant(0)),
+            Commands::LoopSet(constant(4)),
+            Commands::CondGT(params(0), constant(64)),
+            Commands::Add(memory_buffer(0), constant(1)),
+            Commands::CondEnd,
+            Commands::LoopEnd,
+            Commands::End,
+        ]);
+        let ast = to_ast(&script).unwrap();
+        assert_eq!(ast, vec![
+            Stmt::Op(Commands::Set(memory_buffer(0), constant(0))),
+            Stmt::Loop {
+                count: constant(4),
+                body: vec![Stmt::If {
+                    cond: Commands::CondGT(params(0), constant(64)),
+                    body: vec![Stmt::Op(Commands::Add(memory_buffer(0), constant(1)))],
+                }],
+            },
+            Stmt::Op(Commands::End),
+        ]);
+        assert_eq!(from_ast(&ast), script);
+    }
+
+    #[test]
+    fn mismatched_blocks() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::LoopSet(constant(2)), Commands::CondEnd]);
+        assert_eq!(to_ast(&script), Err(AstError::UnmatchedCondEnd { index: 1 }));
+
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::CondE(params(0), constant(1)), Commands::LoopEnd]);
+        assert_eq!(to_ast(&script), Err(AstError::UnmatchedLoopEnd { index: 1 }));
+
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::CondE(params(0), constant(1)), Commands::LoopSet(constant(2)), Commands::LoopEnd]);
+        assert_eq!(to_ast(&script), Err(AstError::UnclosedCond { index: 0 }));
+    }
+}
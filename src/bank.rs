@@ -0,0 +1,134 @@
+/*
+
+Container for several scripts, e.g. one per track of a project
+
+Layout:
+
+    4E 53 42 01     "NSB" + container version
+    NN              number of scripts
+    HH LL ...       length of the first script (16-bit, big endian) followed by its encoded bytes
+    HH LL ...       (and so on for every script)
+
+*/
+
+use alloc::vec::Vec;
+use log::debug;
+use crate::{NSLScript, NslError};
+
+const BANK_HEADER: [u8; 4] = [0x4E, 0x53, 0x42, 0x01];
+
+/// Most scripts a bank can hold, the count is stored in one byte
+pub const MAX_BANK_SCRIPTS: usize = u8::MAX as usize;
+
+/// A bundle of scripts that is encoded into a single container
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScriptBank {
+    pub scripts: Vec<NSLScript>,
+}
+
+impl ScriptBank {
+    /// Creates an empty bank
+    pub fn new() -> ScriptBank {
+        ScriptBank { scripts: Vec::new() }
+    }
+
+    /// Adds a script to the bank
+    pub fn add_script(&mut self, script: NSLScript) {
+        self.scripts.push(script);
+    }
+
+    /// Encodes the bank, length prefixing every script so they can be split apart again.
+    /// Fails if there are more than `MAX_BANK_SCRIPTS` scripts or one of them is 64 KiB or more.
+    pub fn code(&self) -> Result<Vec<u8>, NslError> {
+        let count = self.scripts.len();
+        if count > MAX_BANK_SCRIPTS {
+            return Err(NslError::TooManyScripts { count, max: MAX_BANK_SCRIPTS });
+        }
+        let mut code = BANK_HEADER.to_vec();
+        code.push(count as u8);
+        for script in &self.scripts {
+            let script_code = script.code();
+            debug!("Packing script of {} bytes", script_code.len());
+            let len = u16::try_from(script_code.len())
+                .map_err(|_| NslError::ScriptTooLarge { size: script_code.len(), max: u16::MAX as usize })?;
+            code.extend(len.to_be_bytes());
+            code.extend(script_code);
+        }
+        Ok(code)
+    }
+
+    /// Decodes a bank, reporting offsets relative to the start of the container or of the failing script.
+    /// Bytes left after the last script are reported as `NslError::TrailingData`.
+    pub fn from_u8_vec(data: &[u8]) -> Result<ScriptBank, NslError> {
+        if data.iter().zip(BANK_HEADER).any(|(a, b)| *a != b) {
+            return Err(NslError::BadMagic);
+        }
+        if data.len() < 5 {
+            return Err(NslError::UnexpectedEof { at: 0, needed: 5 });
+        }
+        let mut bank = ScriptBank::new();
+        let mut i = 5;
+        for _ in 0..data[4] {
+            if i + 2 > data.len() {
+                return Err(NslError::UnexpectedEof { at: i, needed: 2 });
+            }
+            let len = u16::from_be_bytes([data[i], data[i + 1]]) as usize;
+            i += 2;
+            if i + len > data.len() {
+                return Err(NslError::UnexpectedEof { at: i, needed: len });
+            }
+            bank.add_script(NSLScript::try_from_u8_vec(&data[i..i + len])?);
+            i += len;
+        }
+        if i < data.len() {
+            return Err(NslError::TrailingData { at: i, len: data.len() - i });
+        }
+        Ok(bank)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn pack_and_unpack() {
+        let mut first = NSLScript::new();
+        first.add_commands(vec![Commands::Set(step_pitch(0), constant(36)), Commands::End]);
+        let mut second = NSLScript::new();
+        second.add_commands(vec![Commands::SelectTrack(constant(1)), Commands::ClearTrack, Commands::End]);
+
+        let mut bank = ScriptBank::new();
+        bank.add_script(first.clone());
+        bank.add_script(second.clone());
+        let code = bank.code().unwrap();
+        assert_eq!(code[..7], [0x4E, 0x53, 0x42, 0x01, 0x02, 0x00, 0x0A]);
+
+        let unpacked = ScriptBank::from_u8_vec(&code).unwrap();
+        assert_eq!(unpacked.scripts, vec![first, second]);
+
+        assert!(matches!(ScriptBank::from_u8_vec(&code[..code.len() - 1]), Err(NslError::UnexpectedEof { at: 19, needed: 9 })));
+        assert!(matches!(ScriptBank::from_u8_vec(&[0x4E, 0x53, 0x4C, 0x01]), Err(NslError::BadMagic)));
+
+        let mut padded = code.clone();
+        padded.extend([0x00, 0x00]);
+        assert!(matches!(ScriptBank::from_u8_vec(&padded), Err(NslError::TrailingData { at: 28, len: 2 })));
+    }
+
+    #[test]
+    fn bank_limits() {
+        let mut bank = ScriptBank::new();
+        for _ in 0..MAX_BANK_SCRIPTS {
+            bank.add_script(NSLScript::new());
+        }
+        assert_eq!(ScriptBank::from_u8_vec(&bank.code().unwrap()).unwrap().scripts.len(), MAX_BANK_SCRIPTS);
+        bank.add_script(NSLScript::new());
+        assert!(matches!(bank.code(), Err(NslError::TooManyScripts { count: 256, max: MAX_BANK_SCRIPTS })));
+
+        let mut large = NSLScript::new();
+        large.add_commands(vec![Commands::ClearAll; u16::MAX as usize]);
+        let bank = ScriptBank { scripts: vec![large] };
+        assert!(matches!(bank.code(), Err(NslError::ScriptTooLarge { size: 65539, max: 65535 })));
+    }
+}
@@ -0,0 +1,229 @@
+/*
+
+Incremental construction of NSL scripts
+
+    let script = NSLScriptBuilder::new()
+        .command(Commands::CondGT(params(0), constant(64)))
+        .command(Commands::Set(step_pitch(0), constant(48)))
+        .end()
+        .auto_close()
+        .build();
+
+Fragments are reusable command sequences without header or End, added with `add_fragment`.
+
+*/
+
+use alloc::vec::Vec;
+use crate::{Commands, Int16, LabelError, NSLScript};
+
+/// A reusable command sequence, with no header and no End.
+/// `Jump` targets are byte offsets from the start of the fragment and are relocated wherever
+/// the fragment is inserted. `JumpRel` targets are relative already and are kept as they are.
+/// Labels are copied verbatim, so a fragment with labels can only be added once per script.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Fragment(pub Vec<Commands>);
+
+impl Fragment {
+    /// Creates a fragment from its commands
+    pub fn new(commands: Vec<Commands>) -> Fragment {
+        Fragment(commands)
+    }
+
+    /// Returns the commands placed at the start of a script, right after the header
+    pub fn instantiate(&self) -> Result<Vec<Commands>, LabelError> {
+        self.relocate(4)
+    }
+
+    /// Returns the commands with their jumps moved to a fragment starting at byte `offset` of a script.
+    /// Fails with `LabelError::JumpOutOfRange` if a jump lands past 0xFFFF.
+    fn relocate(&self, offset: usize) -> Result<Vec<Commands>, LabelError> {
+        self.0.iter().enumerate().map(|(index, command)| match command {
+            Commands::Jump(x) => {
+                let target = x.get_value() as usize + offset;
+                let [hi, lo] = u16::try_from(target)
+                    .map_err(|_| LabelError::JumpOutOfRange { index, target })?
+                    .to_be_bytes();
+                Ok(Commands::Jump(Int16::new(hi, lo)))
+            },
+            _ => Ok(command.clone())
+        }).collect()
+    }
+}
+
+/// Builds a NSLScript one command at a time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NSLScriptBuilder {
+    commands: Vec<Commands>,
+    auto_close: bool,
+}
+
+impl NSLScriptBuilder {
+    /// Creates a builder without any commands
+    pub fn new() -> NSLScriptBuilder {
+        NSLScriptBuilder::default()
+    }
+
+    /// Adds a command
+    pub fn command(&mut self, command: Commands) -> &mut NSLScriptBuilder {
+        self.commands.push(command);
+        self
+    }
+
+    /// Adds several commands, in order
+    pub fn commands<I: IntoIterator<Item = Commands>>(&mut self, commands: I) -> &mut NSLScriptBuilder {
+        self.commands.extend(commands);
+        self
+    }
+
+    /// Adds the commands of a fragment, relocating its jumps to where it lands in the script.
+    /// Fails, adding nothing, if a relocated jump lands past 0xFFFF or if the fragment
+    /// has a label that the script already has (`LabelError::Duplicate`).
+    pub fn add_fragment(&mut self, fragment: &Fragment) -> Result<&mut NSLScriptBuilder, LabelError> {
+        let offset = 4 + self.commands.iter().map(Commands::len).sum::<usize>();
+        let commands = fragment.relocate(offset)?;
+        for command in &commands {
+            if let Commands::Label(name) = command {
+                if self.commands.contains(command) {
+                    return Err(LabelError::Duplicate(name.clone()));
+                }
+            }
+        }
+        Ok(self.commands(commands))
+    }
+
+    /// Adds a label that `Commands::JumpLabel` can target (see `NSLScript::resolve_labels`)
+    pub fn label(&mut self, name: &str) -> &mut NSLScriptBuilder {
+        self.command(Commands::Label(name.into()))
+    }
+
+    /// Adds the End command
+    pub fn end(&mut self) -> &mut NSLScriptBuilder {
+        self.command(Commands::End)
+    }
+
+    /// Makes `build` close the loops and conditionals that are still open, innermost first.
+    /// The closing commands go before the final End, or at the end of the script if there is none.
+    /// Off by default, since a missing close is usually a logic error that `NSLScript::validate` reports.
+    pub fn auto_close(&mut self) -> &mut NSLScriptBuilder {
+        self.auto_close = true;
+        self
+    }
+
+    /// Returns the built script
+    pub fn build(&self) -> NSLScript {
+        let mut commands = self.commands.clone();
+        if self.auto_close {
+            let end = match commands.last() {
+                Some(Commands::End) => commands.pop(),
+                _ => None
+            };
+            let closes: Vec<Commands> = open_blocks(&commands).into_iter().rev().collect();
+            commands.extend(closes);
+            commands.extend(end);
+        }
+        let mut script = NSLScript::new();
+        script.add_commands(commands);
+        script
+    }
+}
+
+/// Returns the commands closing the loops and conditionals left open, outermost first.
+/// Stray closes are left alone for `NSLScript::validate` to report.
+fn open_blocks(commands: &[Commands]) -> Vec<Commands> {
+    let mut open = Vec::new();
+    for command in commands {
+        match command {
+            Commands::LoopSet(_) => open.push(Commands::LoopEnd),
+            Commands::CondEnd | Commands::LoopEnd if open.last() == Some(command) => {
+                open.pop();
+            },
+            Commands::CondEnd | Commands::LoopEnd => {},
+            _ if command.is_conditional() => open.push(Commands::CondEnd),
+            _ => {}
+        }
+    }
+    open
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use crate::vm::Machine;
+
+    #[test]
+    fn auto_close_blocks() {
+        let mut builder = NSLScriptBuilder::new();
+        builder
+            .command(Commands::LoopSet(constant(4)))
+            .command(Commands::CondGT(params(0), constant(64)))
+            .command(Commands::Set(step_pitch(0), constant(48)))
+            .end();
+        // Opt-in: without auto_close the script is built as written
+        assert!(builder.build().validate().is_err());
+
+        let script = builder.auto_close().build();
+        assert_eq!(script.validate(), Ok(()));
+        assert_eq!(script.commands[3..], [Commands::CondEnd, Commands::LoopEnd, Commands::End]);
+
+        let script = NSLScriptBuilder::new()
+            .command(Commands::CondE(memory_buffer(0), constant(1)))
+            .command(Commands::CondEnd)
+            .auto_close()
+            .build();
+        assert_eq!(script.commands.len(), 2);
+    }
+
+    #[test]
+    fn relocated_fragments() {
+        // Runs a randomized velocity four times, as long as param 0 is above 64
+        let fragment = Fragment::new(vec![
+            Commands::Set(memory_buffer(0), constant(0)),
+            Commands::CondGT(params(0), constant(64)),      // 5
+            Commands::JumpRel(8),                           // 10: skips the Set below, to the CondEnd at 18
+            Commands::Set(step_velocity(0), random(127)),
+            Commands::CondEnd,                              // 18
+            Commands::Add(memory_buffer(0), constant(1)),
+            Commands::CondLT(memory_buffer(0), constant(4)),
+            Commands::Jump(Int16::new(0x00, 0x05)),         // 29: back to the CondGT
+            Commands::CondEnd,
+        ]);
+        let start = fragment.instantiate().unwrap();
+        assert_eq!(start[7], Commands::Jump(Int16::new(0x00, 0x09)));
+
+        let mut script = NSLScriptBuilder::new()
+            .add_fragment(&fragment).unwrap()
+            .command(Commands::ClearMemory)
+            .add_fragment(&fragment).unwrap()
+            .end()
+            .build();
+        assert_eq!(script.commands[..9], start[..]);
+        // The second copy starts after the header, the 33 bytes of the first one and the ClearMemory
+        assert_eq!(script.commands[17], Commands::Jump(Int16::new(0x00, 38 + 5)));
+
+        assert_eq!(script.resolve_relative(), Ok(()));
+        assert_eq!(script.validate(), Ok(()));
+        let offsets: Vec<usize> = script.iter_with_offsets().map(|(offset, _)| offset).collect();
+        for copy in [0, 10] {
+            assert_eq!(script.commands[copy + 2], Commands::Jump(Int16::new(0x00, offsets[copy + 4] as u8)));
+            assert_eq!(script.commands[copy + 7], Commands::Jump(Int16::new(0x00, offsets[copy + 1] as u8)));
+        }
+
+        let mut machine = Machine::default();
+        machine.run(&script, 1000).unwrap();
+        assert_eq!(machine.memory[0], 4);
+    }
+
+    #[test]
+    fn fragment_errors() {
+        let far = Fragment::new(vec![Commands::Jump(Int16::new(0xFF, 0xFE))]);
+        assert_eq!(far.instantiate(), Err(LabelError::JumpOutOfRange { index: 0, target: 0x10002 }));
+        let mut builder = NSLScriptBuilder::new();
+        assert_eq!(builder.add_fragment(&far).err(), Some(LabelError::JumpOutOfRange { index: 0, target: 0x10002 }));
+
+        let labeled = Fragment::new(vec![Commands::Label("top".into()), Commands::JumpLabel("top".into())]);
+        builder.add_fragment(&labeled).unwrap();
+        assert_eq!(builder.add_fragment(&labeled).err(), Some(LabelError::Duplicate("top".into())));
+        assert_eq!(builder.build().commands.len(), 2);
+    }
+}
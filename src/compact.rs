@@ -0,0 +1,87 @@
+/*
+
+Compact debug formatting for trace logs
+
+The derived Debug output spells out every wrapper (`Set(StepPitch(Number(0)), Constant(Number(36)))`).
+The compact form keeps the variant names but collapses the values: `Set(pitch[0], 36)`, with `@N`
+for memory buffer references (`mem[@3]`).
+
+*/
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::{Commands, DataSource, NSLScript, SeqKind};
+
+impl DataSource {
+    /// Formats the source for trace logs, e.g. `36`, `@3`, `pitch[0]` or `random(12)`
+    pub fn debug_compact(&self) -> String {
+        if let Some((sequence, x)) = self.as_step() {
+            let name = match sequence {
+                SeqKind::Pitch => "pitch",
+                SeqKind::Velocity => "velocity",
+                SeqKind::Length => "length",
+                SeqKind::Density => "density",
+            };
+            return format!("{}[{}]", name, x);
+        }
+        match self {
+            DataSource::Constant(x) => format!("{}", x),
+            DataSource::Random(x) => format!("random({})", x),
+            DataSource::RandomNote(x) => format!("random_note({})", x),
+            _ => format!("{}[{}]", self.kind().name(), self.inner())
+        }
+    }
+}
+
+impl Commands {
+    /// Formats the command for trace logs, e.g. `Set(pitch[0], 36)` or `Jump(0x0004)`
+    pub fn debug_compact(&self) -> String {
+        let debug = format!("{:?}", self);
+        let variant = debug.split('(').next().unwrap_or_default();
+        let operands: Vec<String> = match self {
+            Commands::Jump(x) => Vec::from([format!("0x{:04X}", x.get_value())]),
+            Commands::Label(name) | Commands::JumpLabel(name) => Vec::from([name.clone()]),
+            Commands::JumpRel(x) => Vec::from([format!("{:+}", x)]),
+            _ => match (self.operands(), self.single_operand()) {
+                (Some((x, y)), _) => Vec::from([x.debug_compact(), y.debug_compact()]),
+                (None, Some(x)) => Vec::from([x.debug_compact()]),
+                (None, None) => return String::from(variant)
+            }
+        };
+        format!("{}({})", variant, operands.join(", "))
+    }
+}
+
+impl NSLScript {
+    /// Formats the commands for trace logs as a compact list, e.g. `[Set(pitch[0], 36), End]`
+    pub fn debug_compact(&self) -> String {
+        let commands: Vec<String> = self.commands.iter().map(Commands::debug_compact).collect();
+        format!("[{}]", commands.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn compact_fixture() {
+        let script = NSLScript::try_from_u8_vec(FIXTURE).unwrap();
+        assert_eq!(script.debug_compact(), concat!(
+            "[Set(mem[0], params[0]), Divide(mem[0], 3), Set(mem[1], params[1]), Divide(mem[1], 3), ",
+            "GenerateEuclidean(mem[0], mem[1]), Set(mem[0], 0), LoopSet(32), CondGT(velocity[@0], 0), ",
+            "Set(mem[1], random_note(40)), Set(pitch[@0], scale[@1]), Add(pitch[@0], 48), Set(length[@0], 1), ",
+            "Set(density[@0], random(49)), CondEnd, Add(mem[0], 1), LoopEnd]"
+        ));
+    }
+
+    #[test]
+    fn compact_commands() {
+        assert_eq!(Commands::Set(step_pitch(0), constant(36)).debug_compact(), "Set(pitch[0], 36)");
+        assert_eq!(Commands::Copy(DataSource::MemoryBuffer(DataValue::Buffer(3)), random(12)).debug_compact(), "Copy(mem[@3], random(12))");
+        assert_eq!(Commands::LoopSet(params(1)).debug_compact(), "LoopSet(params[1])");
+        assert_eq!(Commands::Jump(Int16::new(0x00, 0x04)).debug_compact(), "Jump(0x0004)");
+        assert_eq!(Commands::CondEnd.debug_compact(), "CondEnd");
+    }
+}
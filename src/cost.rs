@@ -0,0 +1,123 @@
+/*
+
+Rough execution cost model for budgeting how much work a script does every step
+
+The numbers are estimates in arbitrary units, not measured device cycles: reading and writing
+values is cheap, clearing a sequence touches every step and the generators are the most expensive.
+
+*/
+
+use alloc::vec::Vec;
+use crate::{Commands, DataSource, DataValue, NSLScript};
+
+impl Commands {
+    /// Returns the estimated cost of executing the command once
+    pub fn cost(&self) -> u32 {
+        match self {
+            Commands::Label(_) | Commands::None => 0,
+            Commands::Set(_, _) | Commands::Copy(_, _) => 2,
+            Commands::Add(_, _) | Commands::Subtract(_, _) | Commands::Multiply(_, _) => 3,
+            Commands::Divide(_, _) => 4,
+            Commands::CondE(_, _)
+            | Commands::CondNE(_, _)
+            | Commands::CondGT(_, _)
+            | Commands::CondLT(_, _)
+            | Commands::CondGTE(_, _)
+            | Commands::CondLTE(_, _) => 3,
+            Commands::ClearMemory => 8,
+            Commands::ClearTrack | Commands::ClearTrackAt(_) => 16,
+            Commands::ClearAll => 24,
+            Commands::QuantizePitch => 32,
+            Commands::GenerateEuclidean(_, _) => 48,
+            Commands::GenerateProgression => 64,
+            Commands::LoopSet(_)
+            | Commands::LoopEnd
+            | Commands::Jump(_)
+            | Commands::JumpLabel(_)
+            | Commands::JumpRel(_)
+            | Commands::SelectTrack(_)
+            | Commands::CondEnd
+            | Commands::End => 1,
+        }
+    }
+}
+
+impl NSLScript {
+    /// Returns the estimated cost of running the script once.
+    /// Loop bodies are multiplied by their repetitions when the LoopSet count is a constant number,
+    /// and counted once otherwise. Conditionals are assumed to pass and jumps are not followed.
+    pub fn total_cost(&self) -> u32 {
+        // Open loops as (repetitions, cost of the body so far), with the whole script at the bottom
+        let mut open: Vec<(u32, u32)> = Vec::from([(1, 0)]);
+        for command in &self.commands {
+            match command {
+                Commands::LoopSet(count) => {
+                    let repetitions = match count {
+                        // The body always runs at least once
+                        DataSource::Constant(DataValue::Number(x)) => (*x as u32).max(1),
+                        _ => 1
+                    };
+                    // LoopSet itself runs once, as part of the enclosing body
+                    let body = &mut open.last_mut().unwrap().1;
+                    *body = body.saturating_add(command.cost());
+                    open.push((repetitions, 0));
+                },
+                Commands::LoopEnd if open.len() > 1 => {
+                    let (repetitions, body) = open.pop().unwrap();
+                    let parent = &mut open.last_mut().unwrap().1;
+                    *parent = parent.saturating_add(body.saturating_add(command.cost()).saturating_mul(repetitions));
+                },
+                _ => {
+                    let body = &mut open.last_mut().unwrap().1;
+                    *body = body.saturating_add(command.cost());
+                }
+            }
+        }
+        // Unclosed loops are counted as if they ended with the script
+        while open.len() > 1 {
+            let (repetitions, body) = open.pop().unwrap();
+            let parent = &mut open.last_mut().unwrap().1;
+            *parent = parent.saturating_add(body.saturating_mul(repetitions));
+        }
+        open[0].1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn loops_multiply_cost() {
+        let mut flat = NSLScript::new();
+        flat.add_commands(vec![
+            Commands::Add(memory_buffer(0), constant(1)),
+            Commands::Add(memory_buffer(0), constant(1)),
+            Commands::End,
+        ]);
+        assert_eq!(flat.total_cost(), 7);
+
+        let mut looped = NSLScript::new();
+        looped.add_commands(vec![
+            Commands::LoopSet(constant(8)),
+            Commands::LoopSet(constant(4)),
+            Commands::Add(memory_buffer(0), constant(1)),
+            Commands::LoopEnd,
+            Commands::LoopEnd,
+            Commands::End,
+        ]);
+        // Inner loop: 1 + 4 * (3 + 1) = 17, outer loop: 1 + 8 * (17 + 1) = 145, then End
+        assert_eq!(looped.total_cost(), 146);
+        assert!(looped.total_cost() > flat.total_cost());
+
+        // Counts read at run time are counted once
+        looped.commands[0] = Commands::LoopSet(params(0));
+        assert_eq!(looped.total_cost(), 20);
+    }
+
+    #[test]
+    fn generators_are_expensive() {
+        assert!(Commands::GenerateProgression.cost() > Commands::Set(params(0), constant(1)).cost());
+        assert!(Commands::euclidean(constant(3), constant(8)).cost() > Commands::Divide(memory_buffer(0), constant(2)).cost());
+    }
+}
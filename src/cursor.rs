@@ -0,0 +1,84 @@
+/*
+
+Lazy decoding of encoded scripts
+
+A CommandCursor walks the encoded bytes and decodes one command per call to `next`, so a
+script can be inspected without building its `Vec<Commands>`.
+
+*/
+
+use crate::{opcode_len, Commands, NSLScript, NslError};
+
+/// Iterator decoding the commands of an encoded script one at a time, created with `CommandCursor::new`.
+/// Stops after the first error.
+#[derive(Debug, Clone)]
+pub struct CommandCursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> CommandCursor<'a> {
+    /// Checks the header of the encoded script and returns a cursor at its first command
+    pub fn new(data: &'a [u8]) -> Result<CommandCursor<'a>, NslError> {
+        NSLScript::check_header(data)?;
+        Ok(CommandCursor { data, offset: 4, failed: false })
+    }
+
+    /// Returns the byte offset of the next command to decode
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl Iterator for CommandCursor<'_> {
+    type Item = Result<Commands, NslError>;
+
+    fn next(&mut self) -> Option<Result<Commands, NslError>> {
+        if self.failed || self.offset >= self.data.len() {
+            return None;
+        }
+        let at = self.offset;
+        let len = opcode_len(self.data[at]);
+        let result = if len == 0 {
+            Err(NslError::UnknownOpcode { at, byte: self.data[at] })
+        } else if at + len > self.data.len() {
+            Err(NslError::UnexpectedEof { at, needed: len })
+        } else {
+            self.offset += len;
+            Ok(Commands::from_slice(&self.data[at..at + len]))
+        };
+        self.failed = result.is_err();
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn count_fixture_commands() {
+        let cursor = CommandCursor::new(FIXTURE).unwrap();
+        assert_eq!(cursor.clone().count(), 16);
+        assert!(cursor.clone().all(|command| command.is_ok()));
+        let loops = cursor.filter(|command| matches!(command, Ok(Commands::LoopSet(_)))).count();
+        assert_eq!(loops, 1);
+    }
+
+    #[test]
+    fn stops_at_errors() {
+        assert!(matches!(CommandCursor::new(&[0x4E, 0x53]), Err(NslError::UnexpectedEof { at: 0, needed: 4 })));
+
+        let data = [0x4E, 0x53, 0x4C, 0x01, 0xA5, 0x42, 0xFF];
+        let mut cursor = CommandCursor::new(&data).unwrap();
+        assert!(matches!(cursor.next(), Some(Ok(Commands::ClearAll))));
+        assert_eq!(cursor.offset(), 5);
+        assert!(matches!(cursor.next(), Some(Err(NslError::UnknownOpcode { at: 5, byte: 0x42 }))));
+        assert!(cursor.next().is_none());
+
+        let mut cursor = CommandCursor::new(&[0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x06]).unwrap();
+        assert!(matches!(cursor.next(), Some(Err(NslError::UnexpectedEof { at: 4, needed: 5 }))));
+    }
+}
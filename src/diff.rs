@@ -0,0 +1,123 @@
+/*
+
+Command level diff between two scripts
+
+*/
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::{Commands, NSLScript};
+
+/// A change between two scripts, as returned by `NSLScript::diff`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptDiff {
+    /// The command at this index of the new script was added
+    Added(usize, Commands),
+    /// The command at this index of the old script was removed
+    Removed(usize, Commands),
+    /// The command at this index of the old script was replaced (old, new)
+    Changed(usize, Commands, Commands),
+}
+
+impl NSLScript {
+    /// Lists the commands that changed from this script to `other`, in script order.
+    /// Uses the longest common subsequence of both command lists, so unchanged commands are never reported.
+    ///
+    /// Within a run of edits between two unchanged commands, removals come before additions, and
+    /// the last removal of the run is paired with its first addition as a single `Changed`,
+    /// at the index of the removed command. Any other removal or addition of the run is reported
+    /// on its own, and edits separated by an unchanged command are never paired.
+    pub fn diff(&self, other: &NSLScript) -> Vec<ScriptDiff> {
+        let (old, new) = (&self.commands, &other.commands);
+        // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+        let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lcs[i][j] = if old[i] == new[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+
+        let mut changes = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                i += 1;
+                j += 1;
+            } else if j < new.len() && (i == old.len() || lcs[i][j + 1] > lcs[i + 1][j]) {
+                let change = match changes.last() {
+                    Some(ScriptDiff::Removed(index, removed)) if *index + 1 == i => {
+                        let change = ScriptDiff::Changed(*index, removed.clone(), new[j].clone());
+                        changes.pop();
+                        change
+                    },
+                    _ => ScriptDiff::Added(j, new[j].clone())
+                };
+                changes.push(change);
+                j += 1;
+            } else {
+                changes.push(ScriptDiff::Removed(i, old[i].clone()));
+                i += 1;
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn added_command() {
+        let mut old = NSLScript::new();
+        old.add_commands(vec![Commands::Set(step_pitch(0), constant(36)), Commands::End]);
+        let mut new = NSLScript::new();
+        new.add_commands(vec![
+            Commands::Set(step_pitch(0), constant(36)),
+            Commands::Set(step_velocity(0), constant(100)),
+            Commands::End,
+        ]);
+        assert_eq!(old.diff(&new), vec![ScriptDiff::Added(1, Commands::Set(step_velocity(0), constant(100)))]);
+        assert_eq!(new.diff(&old), vec![ScriptDiff::Removed(1, Commands::Set(step_velocity(0), constant(100)))]);
+        assert_eq!(old.diff(&old), vec![]);
+    }
+
+    #[test]
+    fn changed_command() {
+        let mut old = NSLScript::new();
+        old.add_commands(vec![Commands::Set(step_pitch(0), constant(36)), Commands::End]);
+        let mut new = NSLScript::new();
+        new.add_commands(vec![Commands::Set(step_pitch(0), constant(48)), Commands::End, Commands::End]);
+        assert_eq!(old.diff(&new), vec![
+            ScriptDiff::Changed(0, Commands::Set(step_pitch(0), constant(36)), Commands::Set(step_pitch(0), constant(48))),
+            ScriptDiff::Added(2, Commands::End),
+        ]);
+    }
+
+    #[test]
+    fn interleaved_edits() {
+        let (a, b, c) = (Commands::ClearMemory, Commands::LoopEnd, Commands::End);
+        let (x, y) = (Commands::Set(step_pitch(0), constant(48)), Commands::Set(step_pitch(1), constant(50)));
+        let mut old = NSLScript::new();
+        old.add_commands(vec![a.clone(), b.clone(), c.clone()]);
+
+        // The removal of a and the addition of x are split by the unchanged b
+        let mut new = NSLScript::new();
+        new.add_commands(vec![b.clone(), x.clone(), c.clone()]);
+        assert_eq!(old.diff(&new), vec![ScriptDiff::Removed(0, a.clone()), ScriptDiff::Added(1, x.clone())]);
+
+        // Two removals then two additions: only b and x are paired
+        let mut new = NSLScript::new();
+        new.add_commands(vec![x.clone(), y.clone(), c.clone()]);
+        assert_eq!(old.diff(&new), vec![
+            ScriptDiff::Removed(0, a.clone()),
+            ScriptDiff::Changed(1, b.clone(), x.clone()),
+            ScriptDiff::Added(1, y.clone()),
+        ]);
+    }
+}
@@ -0,0 +1,153 @@
+/*
+
+Control flow graph of NSL scripts
+
+Nodes are the byte offsets of the commands in the encoded script. Edges that leave the
+script point at `len_bytes()`, the offset right after the last command.
+
+*/
+
+use alloc::vec::Vec;
+use crate::{Commands, NSLScript};
+
+/// Why control can move from one command to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution continues with the next command
+    FallThrough,
+    /// A `Jump` (or `JumpRel`) to its target
+    Jump,
+    /// A `LoopEnd` going back to the first command of the loop body
+    LoopBack,
+    /// A failed conditional skipping to the command after its `CondEnd`
+    CondSkip,
+}
+
+/// An edge between the commands at two byte offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// Control flow graph returned by `NSLScript::control_flow`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    /// Byte offset of every command, in script order
+    pub nodes: Vec<usize>,
+    pub edges: Vec<Edge>,
+}
+
+impl ControlFlowGraph {
+    /// Returns the edges leaving the command at `offset`
+    pub fn edges_from(&self, offset: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.from == offset)
+    }
+
+    /// Returns the edges that go backwards (or to themselves), which is where loops can happen
+    pub fn back_edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(|edge| edge.to <= edge.from)
+    }
+}
+
+impl NSLScript {
+    /// Builds the control flow graph of the script. Unbalanced loops and conditionals only get their fall-through edges.
+    pub fn control_flow(&self) -> ControlFlowGraph {
+        let nodes: Vec<usize> = self.iter_with_offsets().map(|(offset, _)| offset).collect();
+        let end = self.len_bytes();
+        // Offset of the command after the one at `index`
+        let next = |index: usize| nodes.get(index + 1).copied().unwrap_or(end);
+
+        let mut edges = Vec::new();
+        let mut loops: Vec<usize> = Vec::new();
+        for (index, command) in self.commands.iter().enumerate() {
+            let from = nodes[index];
+            match command {
+                Commands::End => continue,
+                Commands::Jump(x) => {
+                    edges.push(Edge { from, to: x.get_value() as usize, kind: EdgeKind::Jump });
+                    continue;
+                },
+                Commands::JumpRel(x) => {
+                    if let Some(to) = from.checked_add_signed(*x as isize) {
+                        edges.push(Edge { from, to, kind: EdgeKind::Jump });
+                    }
+                    continue;
+                },
+                Commands::LoopSet(_) => loops.push(index),
+                Commands::LoopEnd => {
+                    if let Some(start) = loops.pop() {
+                        edges.push(Edge { from, to: next(start), kind: EdgeKind::LoopBack });
+                    }
+                },
+                Commands::CondEnd => {},
+                _ if command.is_conditional() => {
+                    if let Some(cond_end) = matching_cond_end(&self.commands, index) {
+                        edges.push(Edge { from, to: next(cond_end), kind: EdgeKind::CondSkip });
+                    }
+                },
+                _ => {}
+            }
+            edges.push(Edge { from, to: next(index), kind: EdgeKind::FallThrough });
+        }
+        ControlFlowGraph { nodes, edges }
+    }
+}
+
+/// Returns the index of the CondEnd closing the conditional at `index`
+fn matching_cond_end(commands: &[Commands], index: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, command) in commands.iter().enumerate().skip(index) {
+        match command {
+            Commands::CondEnd => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ if command.is_conditional() => depth += 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn loop_back_edge() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::LoopSet(constant(4)),                       // 0x04
+            Commands::CondGT(params(0), constant(64)),            // 0x07
+            Commands::Add(memory_buffer(0), constant(1)),         // 0x0C
+            Commands::CondEnd,                                    // 0x11
+            Commands::LoopEnd,                                    // 0x12
+            Commands::End,                                        // 0x13
+        ]);
+        let graph = script.control_flow();
+        assert_eq!(graph.nodes, vec![0x04, 0x07, 0x0C, 0x11, 0x12, 0x13]);
+        assert_eq!(graph.back_edges().collect::<Vec<_>>(), vec![&Edge { from: 0x12, to: 0x07, kind: EdgeKind::LoopBack }]);
+        assert!(graph.edges.contains(&Edge { from: 0x07, to: 0x12, kind: EdgeKind::CondSkip }));
+        assert!(graph.edges.contains(&Edge { from: 0x12, to: 0x13, kind: EdgeKind::FallThrough }));
+        assert_eq!(graph.edges_from(0x13).count(), 0);
+    }
+
+    #[test]
+    fn jump_edges() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Add(memory_buffer(0), constant(1)),
+            Commands::Jump(Int16::new(0x00, 0x04)),
+        ]);
+        let graph = script.control_flow();
+        assert_eq!(graph.edges, vec![
+            Edge { from: 0x04, to: 0x09, kind: EdgeKind::FallThrough },
+            Edge { from: 0x09, to: 0x04, kind: EdgeKind::Jump },
+        ]);
+    }
+}
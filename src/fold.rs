@@ -0,0 +1,132 @@
+/*
+
+Constant folding
+
+A Set of a constant followed by arithmetic with another constant on the same destination
+always leaves the same value behind, so the pair can be replaced by a single Set:
+
+    SET mem[0] = const 5
+    ADD mem[0] + const 3      ->      SET mem[0] = const 8
+
+*/
+
+use alloc::vec::Vec;
+use crate::{Commands, DataSource, DataValue, NSLScript};
+
+/// Largest value the device stores, results above it saturate or wrap depending on the device mode
+const VALUE_MAX: u8 = 127;
+
+/// Returns the value the arithmetic command leaves in its destination when that destination holds
+/// `value`, or None if it can't be known ahead of time or depends on how the device handles overflow
+fn fold(command: &Commands, value: &DataValue) -> Option<DataValue> {
+    let operand = match command.operands() {
+        Some((_, DataSource::Constant(operand))) => operand,
+        _ => return None
+    };
+    let result = match command {
+        Commands::Add(_, _) => value.saturating_add(operand, u8::MAX),
+        Commands::Subtract(_, _) if value.as_u8() >= operand.as_u8() => value.saturating_sub(operand, u8::MAX),
+        Commands::Multiply(_, _) => value.saturating_mul(operand, u8::MAX),
+        Commands::Divide(_, _) => value.saturating_div(operand, u8::MAX),
+        _ => None
+    }?;
+    (result.as_u8() <= VALUE_MAX).then_some(result)
+}
+
+impl NSLScript {
+    /// Merges every Set of a constant with the arithmetic on constants that directly follows it on the
+    /// same destination. Only destinations with a fixed index (not `@N`) are folded, and results that
+    /// would overflow or divide by 0 are left for the device.
+    /// Scripts with `Jump` or `JumpRel` commands are left untouched, since removing commands would move their targets.
+    pub fn fold_constants(&mut self) {
+        if self.commands.iter().any(|command| matches!(command, Commands::Jump(_) | Commands::JumpRel(_))) {
+            return;
+        }
+        let mut folded: Vec<Commands> = Vec::with_capacity(self.commands.len());
+        for command in self.commands.drain(..) {
+            if let Some(Commands::Set(destination, DataSource::Constant(value))) = folded.last_mut() {
+                let foldable = command.operands().is_some_and(|(x, _)| x == destination)
+                    && destination.is_writable()
+                    && destination.inner().is_number();
+                // Buffer references in `value` aren't folded either, `fold` returns None for them
+                if let Some(result) = fold(&command, value).filter(|_| foldable) {
+                    *value = result;
+                    continue;
+                }
+            }
+            folded.push(command);
+        }
+        self.commands = folded;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::vm::Machine;
+
+    #[test]
+    fn fold_arithmetic() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(memory_buffer(0), constant(5)),
+            Commands::Add(memory_buffer(0), constant(3)),
+            Commands::Multiply(memory_buffer(0), constant(4)),
+            Commands::Set(step_pitch(2), constant(48)),
+            Commands::Subtract(step_pitch(2), constant(12)),
+            Commands::Divide(step_pitch(2), constant(5)),
+            Commands::Set(step_velocity(0), memory_buffer(0)),
+            Commands::End,
+        ]);
+        let original = script.clone();
+        script.fold_constants();
+        assert_eq!(script.commands, vec![
+            Commands::Set(memory_buffer(0), constant(32)),
+            Commands::Set(step_pitch(2), constant(7)),
+            Commands::Set(step_velocity(0), memory_buffer(0)),
+            Commands::End,
+        ]);
+        assert!(script.len_bytes() < original.len_bytes());
+
+        let mut before = Machine::default();
+        before.run(&original, 100).unwrap();
+        let mut after = Machine::default();
+        after.run(&script, 100).unwrap();
+        assert_eq!(before.memory, after.memory);
+        assert_eq!(before.tracks, after.tracks);
+    }
+
+    #[test]
+    fn unfoldable_arithmetic() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            // Overflow, underflow and division by 0 depend on the device
+            Commands::Set(memory_buffer(0), constant(100)),
+            Commands::Add(memory_buffer(0), constant(100)),
+            Commands::Set(memory_buffer(1), constant(3)),
+            Commands::Subtract(memory_buffer(1), constant(5)),
+            Commands::Set(memory_buffer(2), constant(3)),
+            Commands::Divide(memory_buffer(2), constant(0)),
+            // Other destination, buffer references and dynamic destinations
+            Commands::Set(memory_buffer(3), constant(3)),
+            Commands::Add(memory_buffer(4), constant(1)),
+            Commands::Add(memory_buffer(3), memory_buffer(1)),
+            Commands::Set(DataSource::MemoryBuffer(DataValue::Buffer(0)), constant(1)),
+            Commands::Add(DataSource::MemoryBuffer(DataValue::Buffer(0)), constant(1)),
+            Commands::End,
+        ]);
+        let original = script.clone();
+        script.fold_constants();
+        assert_eq!(script, original);
+
+        let mut jumping = NSLScript::new();
+        jumping.add_commands(vec![
+            Commands::Set(memory_buffer(0), constant(1)),
+            Commands::Add(memory_buffer(0), constant(1)),
+            Commands::Jump(Int16::new(0x00, 0x04)),
+        ]);
+        let original = jumping.clone();
+        jumping.fold_constants();
+        assert_eq!(jumping, original);
+    }
+}
@@ -0,0 +1,111 @@
+/*
+
+Static analysis of the memory buffer slots a script touches
+
+*/
+
+use alloc::collections::BTreeSet;
+use crate::{Commands, DataSource, DataValue, NSLScript, MEMORY_BUFFER_MAX};
+
+/// Memory buffer slots read and written by a script, as returned by `NSLScript::memory_footprint`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryFootprint {
+    /// Slots whose value is read, either as an operand or through an `@N` reference
+    pub reads: BTreeSet<u8>,
+    /// Slots written by Set, Copy, arithmetic or the clear commands
+    pub writes: BTreeSet<u8>,
+    /// Set when a `mem[@N]` destination is written, so the written slot is only known at run time
+    pub dynamic_writes: bool,
+}
+
+impl MemoryFootprint {
+    /// Records the slots an operand reads, plus the slot it writes if it is a destination
+    fn add(&mut self, source: &DataSource, read: bool, write: bool) {
+        if let DataValue::Buffer(x) = source.inner() {
+            // The index is itself read from the memory buffer
+            self.reads.insert(*x);
+        }
+        match source {
+            DataSource::MemoryBuffer(DataValue::Number(x)) => {
+                if read {
+                    self.reads.insert(*x);
+                }
+                if write {
+                    self.writes.insert(*x);
+                }
+            },
+            DataSource::MemoryBuffer(DataValue::Buffer(_)) => {
+                self.dynamic_writes |= write;
+            },
+            _ => {}
+        }
+    }
+}
+
+impl NSLScript {
+    /// Returns the memory buffer slots the script reads and writes.
+    /// The first operand of Set and Copy is a write, the first operand of arithmetic commands is read and written.
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        let mut footprint = MemoryFootprint::default();
+        for command in &self.commands {
+            match command {
+                Commands::Set(x, y) | Commands::Copy(x, y) => {
+                    footprint.add(x, false, true);
+                    footprint.add(y, true, false);
+                },
+                Commands::ClearMemory | Commands::ClearAll => {
+                    footprint.writes.extend(0..=MEMORY_BUFFER_MAX);
+                },
+                _ if command.is_arithmetic() => {
+                    if let Some((x, y)) = command.operands() {
+                        footprint.add(x, true, true);
+                        footprint.add(y, true, false);
+                    }
+                },
+                _ => {
+                    if let Some((x, y)) = command.operands() {
+                        footprint.add(x, true, false);
+                        footprint.add(y, true, false);
+                    } else if let Some(x) = command.single_operand() {
+                        footprint.add(x, true, false);
+                    }
+                }
+            }
+        }
+        footprint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeSet;
+    use crate::*;
+
+    #[test]
+    fn fixture_footprint() {
+        let script = NSLScript::try_from_u8_vec(FIXTURE).unwrap();
+        let footprint = script.memory_footprint();
+        assert_eq!(footprint.reads, BTreeSet::from([0, 1]));
+        assert_eq!(footprint.writes, BTreeSet::from([0, 1]));
+        assert!(!footprint.dynamic_writes);
+    }
+
+    #[test]
+    fn reads_and_writes() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            // Set only writes its destination
+            Commands::Set(memory_buffer(4), constant(1)),
+            Commands::Copy(memory_buffer(5), memory_buffer(6)),
+            Commands::CondE(params(0), DataSource::Constant(DataValue::Buffer(7))),
+            Commands::Add(DataSource::MemoryBuffer(DataValue::Buffer(8)), constant(1)),
+        ]);
+        let footprint = script.memory_footprint();
+        assert_eq!(footprint.reads, BTreeSet::from([6, 7, 8]));
+        assert_eq!(footprint.writes, BTreeSet::from([4, 5]));
+        assert!(footprint.dynamic_writes);
+
+        script.add_command(Commands::ClearMemory);
+        assert_eq!(script.memory_footprint().writes.len(), MEMORY_BUFFER_MAX as usize + 1);
+    }
+}
@@ -0,0 +1,150 @@
+/*
+
+Host side previews of NGEN's generators
+
+*/
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Distributes `pulses` as evenly as possible over `steps` using Bjorklund's algorithm,
+/// matching the pattern `Commands::GenerateEuclidean` writes on the device.
+///
+/// `pulses` is clamped to `steps`, and a pattern with 0 steps is empty.
+pub fn euclidean(pulses: u8, steps: u8) -> Vec<bool> {
+    let pulses = pulses.min(steps) as usize;
+    let steps = steps as usize;
+    if pulses == 0 {
+        return vec![false; steps];
+    }
+
+    // Start with one group per step and keep appending the remainder groups to the
+    // leading groups until at most one remainder group is left
+    let mut groups: Vec<Vec<bool>> = vec![vec![true]; pulses];
+    let mut remainder: Vec<Vec<bool>> = vec![vec![false]; steps - pulses];
+    while remainder.len() > 1 {
+        let count = groups.len().min(remainder.len());
+        let leftover = if groups.len() > count {
+            groups.split_off(count)
+        } else {
+            remainder.split_off(count)
+        };
+        for (group, tail) in groups.iter_mut().zip(remainder) {
+            group.extend(tail);
+        }
+        remainder = leftover;
+    }
+
+    groups.into_iter().chain(remainder).flatten().collect()
+}
+
+/// The notes of a scale as 12 semitones starting from the root (C)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale {
+    pub semitones: [bool; 12],
+}
+
+impl Scale {
+    /// Creates a scale from the semitones (0 - 11) it contains
+    pub fn from_semitones(semitones: &[u8]) -> Scale {
+        let mut scale = Scale { semitones: [false; 12] };
+        for semitone in semitones {
+            scale.semitones[(*semitone % 12) as usize] = true;
+        }
+        scale
+    }
+
+    /// All 12 semitones
+    pub fn chromatic() -> Scale {
+        Scale { semitones: [true; 12] }
+    }
+
+    /// Major (ionian) scale
+    pub fn major() -> Scale {
+        Scale::from_semitones(&[0, 2, 4, 5, 7, 9, 11])
+    }
+
+    /// Natural minor (aeolian) scale
+    pub fn minor() -> Scale {
+        Scale::from_semitones(&[0, 2, 3, 5, 7, 8, 10])
+    }
+
+    /// Returns true if the MIDI note is part of the scale
+    pub fn contains(&self, note: u8) -> bool {
+        self.semitones[(note % 12) as usize]
+    }
+
+    /// Snaps a MIDI note (0 - 127) to the nearest note of the scale, preferring the lower note on ties.
+    /// Notes are returned unchanged if the scale is empty.
+    pub fn quantize(&self, note: u8) -> u8 {
+        let note = note.min(127);
+        for distance in 0..12 {
+            if let Some(lower) = note.checked_sub(distance) {
+                if self.contains(lower) {
+                    return lower;
+                }
+            }
+            let upper = note + distance;
+            if upper <= 127 && self.contains(upper) {
+                return upper;
+            }
+        }
+        note
+    }
+}
+
+impl Default for Scale {
+    fn default() -> Scale {
+        Scale::chromatic()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders a pattern as `x` for pulses and `.` for rests
+    fn render(pattern: &[bool]) -> String {
+        pattern.iter().map(|pulse| if *pulse { 'x' } else { '.' }).collect()
+    }
+
+    #[test]
+    fn euclidean_patterns() {
+        assert_eq!(render(&euclidean(3, 8)), "x..x..x.");
+        assert_eq!(render(&euclidean(5, 8)), "x.xx.xx.");
+        assert_eq!(render(&euclidean(4, 16)), "x...x...x...x...");
+        assert_eq!(render(&euclidean(8, 8)), "xxxxxxxx");
+        assert_eq!(render(&euclidean(0, 4)), "....");
+    }
+
+    #[test]
+    fn scale_quantize() {
+        let major = Scale::major();
+        // C#4 and F#4 are out of key, D#4 is between D and E
+        assert_eq!(major.quantize(61), 60);
+        assert_eq!(major.quantize(63), 62);
+        assert_eq!(major.quantize(66), 65);
+        assert_eq!(major.quantize(64), 64);
+        // B is in the scale, the octave above wraps around
+        assert_eq!(major.quantize(71), 71);
+        assert_eq!(Scale::minor().quantize(64), 63);
+        assert_eq!(Scale::chromatic().quantize(61), 61);
+        // 127 is a G, which is in both scales
+        assert_eq!(major.quantize(127), 127);
+        assert_eq!(Scale::from_semitones(&[]).quantize(50), 50);
+        assert_eq!(Scale::from_semitones(&[11]).quantize(0), 11);
+    }
+
+    #[test]
+    fn euclidean_edge_cases() {
+        assert!(euclidean(3, 0).is_empty());
+        assert_eq!(euclidean(12, 5), vec![true; 5]);
+        for steps in 0..=32 {
+            for pulses in 0..=steps {
+                let pattern = euclidean(pulses, steps);
+                assert_eq!(pattern.len(), steps as usize);
+                assert_eq!(pattern.iter().filter(|pulse| **pulse).count(), pulses as usize);
+            }
+        }
+    }
+}
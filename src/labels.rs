@@ -0,0 +1,157 @@
+/*
+
+Label based jump resolution
+
+Hand-computing byte offsets for `Commands::Jump` is error prone, so scripts can mark
+positions with `NSLScript::add_label` and jump to them with `Commands::JumpLabel`.
+`NSLScript::resolve_labels` then rewrites them into concrete jumps.
+
+`Commands::JumpRel` jumps by a byte offset from its own position instead, which keeps
+fragments relocatable. `NSLScript::resolve_relative` rewrites them into concrete jumps.
+
+*/
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use log::debug;
+use crate::{Commands, Int16, NSLScript};
+
+/// Errors reported by `NSLScript::resolve_labels`, `NSLScript::resolve_relative`, `NSLScript::append`
+/// and when adding a `Fragment`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelError {
+    /// A JumpLabel refers to a label that was never added
+    Undefined(String),
+    /// The same label was added more than once
+    Duplicate(String),
+    /// The JumpRel at `index` lands before the start of the script or past 0xFFFF
+    RelativeOutOfRange { index: usize, offset: i16 },
+    /// The Jump at `index` of the appended script or fragment is relocated to `target`, past 0xFFFF
+    JumpOutOfRange { index: usize, target: usize },
+}
+
+impl NSLScript {
+    // Mark the current end of the script as a jump target
+    pub fn add_label(&mut self, name: &str) {
+        self.commands.push(Commands::Label(name.to_string()));
+    }
+
+    // Replace every JumpLabel with a Jump to the byte offset of its label and remove the labels
+    pub fn resolve_labels(&mut self) -> Result<(), LabelError> {
+        let mut labels: Vec<(&str, usize)> = Vec::new();
+        for (offset, command) in self.iter_with_offsets() {
+            if let Commands::Label(name) = command {
+                if labels.iter().any(|(label, _)| label == name) {
+                    return Err(LabelError::Duplicate(name.clone()));
+                }
+                labels.push((name, offset));
+            }
+        }
+
+        let mut targets: Vec<Option<usize>> = Vec::new();
+        for command in &self.commands {
+            targets.push(match command {
+                Commands::JumpLabel(name) => match labels.iter().find(|(label, _)| label == name) {
+                    Some((_, target)) => Some(*target),
+                    None => return Err(LabelError::Undefined(name.clone()))
+                },
+                _ => None
+            });
+        }
+
+        let commands = core::mem::take(&mut self.commands);
+        for (command, target) in commands.into_iter().zip(targets) {
+            match (command, target) {
+                (Commands::Label(_), _) => {},
+                (Commands::JumpLabel(name), Some(target)) => {
+                    debug!("Resolved label {} to {:#06x}", name, target);
+                    self.commands.push(Commands::Jump(Int16::new((target >> 8) as u8, target as u8)));
+                },
+                (command, _) => self.commands.push(command)
+            }
+        }
+        Ok(())
+    }
+
+    // Replace every JumpRel with a Jump to the byte offset it points at, counted from the start of the JumpRel
+    pub fn resolve_relative(&mut self) -> Result<(), LabelError> {
+        let mut targets: Vec<Option<u16>> = Vec::new();
+        for (index, (offset, command)) in self.iter_with_offsets().enumerate() {
+            targets.push(match command {
+                Commands::JumpRel(x) => match offset.checked_add_signed(*x as isize).and_then(|target| u16::try_from(target).ok()) {
+                    Some(target) => Some(target),
+                    None => return Err(LabelError::RelativeOutOfRange { index, offset: *x })
+                },
+                _ => None
+            });
+        }
+        for (command, target) in self.commands.iter_mut().zip(targets) {
+            if let Some(target) = target {
+                debug!("Resolved {} to {:#06x}", command, target);
+                let [hi, lo] = target.to_be_bytes();
+                *command = Commands::Jump(Int16::new(hi, lo));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn resolve_back_jump() {
+        let mut script = NSLScript::new();
+        script.add_command(Commands::Set(memory_buffer(0), constant(0)));
+        script.add_label("loop");
+        script.add_command(Commands::Add(memory_buffer(0), constant(1)));
+        script.add_command(Commands::CondLT(memory_buffer(0), constant(8)));
+        script.add_command(Commands::JumpLabel("loop".to_string()));
+        script.add_command(Commands::CondEnd);
+        script.add_command(Commands::End);
+
+        script.resolve_labels().unwrap();
+        assert_eq!(script.commands.len(), 6);
+        match &script.commands[3] {
+            // Header (4) + Set (5)
+            Commands::Jump(target) => assert_eq!(target.get_value(), 9),
+            cmd => panic!("Expected Jump, got {:?}", cmd)
+        }
+    }
+
+    #[test]
+    fn label_errors() {
+        let mut script = NSLScript::new();
+        script.add_command(Commands::JumpLabel("missing".to_string()));
+        assert_eq!(script.resolve_labels(), Err(LabelError::Undefined("missing".to_string())));
+
+        let mut script = NSLScript::new();
+        script.add_label("twice");
+        script.add_label("twice");
+        assert_eq!(script.resolve_labels(), Err(LabelError::Duplicate("twice".to_string())));
+    }
+
+    #[test]
+    fn resolve_relative_jumps() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(memory_buffer(0), constant(0)),       // 0x04
+            Commands::Add(memory_buffer(0), constant(1)),       // 0x09
+            Commands::CondLT(memory_buffer(0), constant(8)),    // 0x0E
+            Commands::JumpRel(-10),                             // 0x13
+            Commands::CondEnd,                                  // 0x16
+            Commands::JumpRel(3),                               // 0x17
+            Commands::End,
+        ]);
+        let original = script.clone();
+        script.resolve_relative().unwrap();
+        assert_eq!(script.commands[3], Commands::Jump(Int16::new(0x00, 0x09)));
+        assert_eq!(script.commands[5], Commands::Jump(Int16::new(0x00, 0x1A)));
+        assert_eq!(script.commands[..3], original.commands[..3]);
+
+        let mut script = NSLScript::new();
+        script.add_command(Commands::JumpRel(-5));
+        assert_eq!(script.resolve_relative(), Err(LabelError::RelativeOutOfRange { index: 0, offset: -5 }));
+    }
+}
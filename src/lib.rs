@@ -1,7 +1,46 @@
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![allow(dead_code)]
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
 use log::{info, debug};
 
+pub mod asm;
+pub mod ast;
+pub mod gen;
+pub mod meta;
+pub mod vm;
+#[cfg(feature = "test-vectors")]
+pub mod test_vectors;
+mod bank;
+mod compact;
+mod builder;
+mod cost;
+mod cursor;
+mod diff;
+mod flow;
+mod fold;
+mod footprint;
+mod labels;
+mod sysex;
+mod validate;
+
+pub use bank::{ScriptBank, MAX_BANK_SCRIPTS};
+pub use builder::{Fragment, NSLScriptBuilder};
+pub use cursor::CommandCursor;
+pub use diff::ScriptDiff;
+pub use flow::{ControlFlowGraph, Edge, EdgeKind};
+pub use footprint::MemoryFootprint;
+pub use labels::LabelError;
+pub use meta::ScriptMeta;
+pub use sysex::{decode_7bit, encode_7bit};
+pub use validate::{JumpError, ValidationError, ValidationWarning, NGEN_MAX_LOOP_DEPTH, NGEN_MAX_NESTING};
+
 /*
 
 ngen_nsl - Rust Library for encoding / decoding NSL scripts for NGEN
@@ -23,8 +62,12 @@ fn clip_u8(value: u8, min: u8, max: u8) -> u8 {
 }
 
 
+/// Highest index of the Memory Buffer (32 slots)
+pub const MEMORY_BUFFER_MAX: u8 = 31;
+
 /// DataValue is a value that can be either a direct number or a index to a value in the Memory Buffer
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DataValue {
     Number(u8),
     Buffer(u8)
@@ -43,7 +86,8 @@ impl DataValue {
         dv
     }
 
-    /// Encodes the DataValue into a u8 value
+    /// Encodes the DataValue into a u8 value, clamping numbers to 127 and buffer indexes to `MEMORY_BUFFER_MAX`
+    /// so the byte always decodes back to the same kind of value
     pub fn code(&self) -> u8 {
         match self {
             DataValue::Number(x) =>  {
@@ -51,14 +95,51 @@ impl DataValue {
                 if value > 127 {
                     value = 127;
                 }
-                0x00 + value
+                value
             },
             DataValue::Buffer(x) => {
-                x + 0x80
+                let mut index = *x;
+                if index > MEMORY_BUFFER_MAX {
+                    debug!("Clipping buffer index {} to {}", index, MEMORY_BUFFER_MAX);
+                    index = MEMORY_BUFFER_MAX;
+                }
+                index + 0x80
             }
         }
     }
 
+    /// Returns true if the value is an index into the Memory Buffer
+    pub fn is_buffer(&self) -> bool {
+        matches!(self, DataValue::Buffer(_))
+    }
+
+    /// Returns true if the value is a direct number
+    pub fn is_number(&self) -> bool {
+        matches!(self, DataValue::Number(_))
+    }
+
+    /// Returns the underlying raw value (the number or the buffer index)
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            DataValue::Number(x) => *x,
+            DataValue::Buffer(x) => *x
+        }
+    }
+
+    /// Interprets a number as a bipolar value centered on 64 (0 - 127 maps to -64 - 63).
+    /// Returns None for memory buffer references, whose value is only known on the device.
+    pub fn as_signed(&self) -> Option<i8> {
+        match self {
+            DataValue::Number(x) => Some((*x).min(127) as i8 - 64),
+            DataValue::Buffer(_) => None
+        }
+    }
+
+    /// Creates a number from a bipolar value (-64 - 63), clamping values outside the range
+    pub fn from_signed(value: i8) -> DataValue {
+        DataValue::Number((value.clamp(-64, 63) + 64) as u8)
+    }
+
     /// Clips the value between a minimum and maximum value according to the type of DataValue
     pub fn clip(&mut self, min: u8, max: u8) {
         match self {
@@ -66,11 +147,64 @@ impl DataValue {
             DataValue::Buffer(x) => *x = clip_u8(*x, min, max)
         }
     }
+
+    /// Applies `op` to two numbers and clamps the result to 0 - `max`.
+    /// Memory buffer references are only known on the device, so they give None.
+    fn saturating_op(&self, other: &DataValue, max: u8, op: fn(u16, u16) -> Option<u16>) -> Option<DataValue> {
+        match (self, other) {
+            (DataValue::Number(x), DataValue::Number(y)) => {
+                let result = op(*x as u16, *y as u16)?;
+                Some(DataValue::Number(result.min(max as u16) as u8))
+            },
+            _ => None
+        }
+    }
+
+    /// Adds two numbers the way the device does, clamping the sum to `max` (usually the destination's `max()`).
+    /// Returns None if either value is a memory buffer reference.
+    pub fn saturating_add(&self, other: &DataValue, max: u8) -> Option<DataValue> {
+        self.saturating_op(other, max, |x, y| Some(x + y))
+    }
+
+    /// Subtracts two numbers, clamping the difference to 0 - `max`.
+    /// Returns None if either value is a memory buffer reference.
+    pub fn saturating_sub(&self, other: &DataValue, max: u8) -> Option<DataValue> {
+        self.saturating_op(other, max, |x, y| Some(x.saturating_sub(y)))
+    }
+
+    /// Multiplies two numbers, clamping the product to `max`.
+    /// Returns None if either value is a memory buffer reference.
+    pub fn saturating_mul(&self, other: &DataValue, max: u8) -> Option<DataValue> {
+        self.saturating_op(other, max, |x, y| Some(x * y))
+    }
+
+    /// Divides two numbers, truncating the quotient and clamping it to `max`.
+    /// Returns None if either value is a memory buffer reference or `other` is 0.
+    pub fn saturating_div(&self, other: &DataValue, max: u8) -> Option<DataValue> {
+        self.saturating_op(other, max, |x, y| x.checked_div(y))
+    }
+}
+
+impl Default for DataValue {
+    /// The number 0
+    fn default() -> DataValue {
+        DataValue::Number(0)
+    }
 }
 
+impl fmt::Display for DataValue {
+    /// Formats the value as a plain number, or as `@N` for a memory buffer reference
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataValue::Number(x) => write!(f, "{}", x),
+            DataValue::Buffer(x) => write!(f, "@{}", x)
+        }
+    }
+}
 
 /// DataSource is a source of data that can be used in the NSL script as a parameter for commands.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DataSource {
     /// A constant value (0 - 127)
     Constant(DataValue),
@@ -97,47 +231,89 @@ pub enum DataSource {
 }
 
 impl DataSource {
-    /// Encodes the DataSource into a `Vec<u8>` value
-    pub fn code(&mut self) -> Vec<u8> {
-        debug!("Converting Data Source: {:?}", self);
-        self.validate();
-        let code: Vec<u8> = 
+    /// Creates a DataSource of the given kind
+    pub fn new(kind: DataSourceKind, value: DataValue) -> DataSource {
+        match kind {
+            DataSourceKind::Constant => DataSource::Constant(value),
+            DataSourceKind::Random => DataSource::Random(value),
+            DataSourceKind::StepPitch => DataSource::StepPitch(value),
+            DataSourceKind::StepVelocity => DataSource::StepVelocity(value),
+            DataSourceKind::StepLength => DataSource::StepLength(value),
+            DataSourceKind::StepDensity => DataSource::StepDensity(value),
+            DataSourceKind::MemoryBuffer => DataSource::MemoryBuffer(value),
+            DataSourceKind::Params => DataSource::Params(value),
+            DataSourceKind::Scale => DataSource::Scale(value),
+            DataSourceKind::FullScale => DataSource::FullScale(value),
+            DataSourceKind::RandomNote => DataSource::RandomNote(value)
+        }
+    }
+
+    /// Returns the kind of the DataSource, without its value
+    pub fn kind(&self) -> DataSourceKind {
         match self {
-            DataSource::Constant(x) => vec![0x00, x.code()],
-            DataSource::Random(x) => vec![0x01, x.code()],
-            DataSource::StepPitch(x) => vec![0x02, x.code()],
-            DataSource::StepVelocity(x) => vec![0x03, x.code()],
-            DataSource::StepLength(x) => vec![0x04, x.code()],
-            DataSource::StepDensity(x) => vec![0x05, x.code()],
-            DataSource::MemoryBuffer(x) => vec![0x06, x.code()],
-            DataSource::Params(x) => vec![0x07, x.code()],
-            DataSource::Scale(x) => vec![0x08, x.code()],
-            DataSource::FullScale(x) => vec![0x09, x.code()],
-            DataSource::RandomNote(x) => vec![0x0A, x.code()]
+            DataSource::Constant(_) => DataSourceKind::Constant,
+            DataSource::Random(_) => DataSourceKind::Random,
+            DataSource::StepPitch(_) => DataSourceKind::StepPitch,
+            DataSource::StepVelocity(_) => DataSourceKind::StepVelocity,
+            DataSource::StepLength(_) => DataSourceKind::StepLength,
+            DataSource::StepDensity(_) => DataSourceKind::StepDensity,
+            DataSource::MemoryBuffer(_) => DataSourceKind::MemoryBuffer,
+            DataSource::Params(_) => DataSourceKind::Params,
+            DataSource::Scale(_) => DataSourceKind::Scale,
+            DataSource::FullScale(_) => DataSourceKind::FullScale,
+            DataSource::RandomNote(_) => DataSourceKind::RandomNote
+        }
+    }
 
-        };
+    /// Returns the sequence and step index of the step sources, or None for every other source
+    pub fn as_step(&self) -> Option<(SeqKind, &DataValue)> {
+        match self {
+            DataSource::StepPitch(x) => Some((SeqKind::Pitch, x)),
+            DataSource::StepVelocity(x) => Some((SeqKind::Velocity, x)),
+            DataSource::StepLength(x) => Some((SeqKind::Length, x)),
+            DataSource::StepDensity(x) => Some((SeqKind::Density, x)),
+            _ => None
+        }
+    }
+
+    /// Encodes the DataSource into a `Vec<u8>` value.
+    /// Only the byte format is enforced (see `DataValue::code`): numbers above 127 are encoded as 127 and
+    /// buffer indexes above `MEMORY_BUFFER_MAX` as `MEMORY_BUFFER_MAX`. The maximum of the source itself
+    /// isn't applied, so `params(5)` is encoded as 5: call `validate` first to clip it (see `would_clip`).
+    pub fn code(&self) -> Vec<u8> {
+        debug!("Converting Data Source: {:?}", self);
+        let code: Vec<u8> = self.to_bytes().to_vec();
         debug!("> Converted Data Source to Vec<u8>: {:?} > {:?}", self, code);
         code
     }
 
+    /// Encodes the DataSource into its (kind, value) bytes without allocating, like `code`
+    pub fn to_bytes(&self) -> [u8; 2] {
+        [self.kind().code(), self.inner().code()]
+    }
+
+    /// Decodes the (kind, value) bytes of a DataSource, see `from_slice`
+    pub fn from_bytes(bytes: [u8; 2]) -> DataSource {
+        DataSource::from_slice(&bytes)
+    }
+
     /// Decodes a `Vec<u8>` value into a DataSource
     pub fn from_u8_vec(data: Vec<u8>) -> DataSource {
+        DataSource::from_slice(&data)
+    }
+
+    /// Decodes the (kind, value) bytes of a DataSource
+    pub fn from_slice(data: &[u8]) -> DataSource {
         debug!("Converting u8 data to DataSource: {:?}", data);
         debug!("> Data size: {:?}", data.len());
+        if data.len() < 2 {
+            debug!("> Not enough data for a DataSource");
+            return DataSource::Constant(DataValue::from_u8(0));
+        }
         let value = DataValue::from_u8(data[1]);
-        let ds = match data[0] {
-            0x00 => DataSource::Constant(value),
-            0x01 => DataSource::Random(value),
-            0x02 => DataSource::StepPitch(value),
-            0x03 => DataSource::StepVelocity(value),
-            0x04 => DataSource::StepLength(value),
-            0x05 => DataSource::StepDensity(value),
-            0x06 => DataSource::MemoryBuffer(value),
-            0x07 => DataSource::Params(value),
-            0x08 => DataSource::Scale(value),
-            0x09 => DataSource::FullScale(value),
-            0x0A => DataSource::RandomNote(value),
-            _ => DataSource::Constant(DataValue::from_u8(0))
+        let ds = match DataSourceKind::try_from(data[0]) {
+            Ok(kind) => DataSource::new(kind, value),
+            Err(_) => DataSource::Constant(DataValue::from_u8(0))
         };
         debug!("> Converted Vec<u8> to DataSource: {:?} > {:?}", data, ds);
         ds
@@ -160,31 +336,215 @@ impl DataSource {
         }
     }
 
+    /// Returns true if both sources encode to the same bytes once validated, which is all the device sees.
+    /// Unlike `==`, values that are clipped to the same number compare equal.
+    pub fn same_encoding(&self, other: &DataSource) -> bool {
+        let (mut a, mut b) = (self.clone(), other.clone());
+        a.validate();
+        b.validate();
+        a.code() == b.code()
+    }
+
+    /// Describes the source with its valid range and value, e.g. `Step Pitch [0–31]: 4`.
+    /// Memory buffer references are described as `Scale <- mem[3]`.
+    pub fn describe(&self) -> String {
+        let title = self.kind().title();
+        match self.inner() {
+            DataValue::Number(x) => format!("{} [0–{}]: {}", title, self.max(), x),
+            DataValue::Buffer(x) => format!("{} <- mem[{}]", title, x)
+        }
+    }
+
+    /// Returns the DataValue wrapped by the DataSource
+    pub fn inner(&self) -> &DataValue {
+        match self {
+            DataSource::Constant(x)
+            | DataSource::Random(x)
+            | DataSource::StepPitch(x)
+            | DataSource::StepVelocity(x)
+            | DataSource::StepLength(x)
+            | DataSource::StepDensity(x)
+            | DataSource::MemoryBuffer(x)
+            | DataSource::Params(x)
+            | DataSource::Scale(x)
+            | DataSource::FullScale(x)
+            | DataSource::RandomNote(x) => x
+        }
+    }
+
+    /// Returns a mutable reference to the DataValue wrapped by the DataSource
+    pub fn inner_mut(&mut self) -> &mut DataValue {
+        match self {
+            DataSource::Constant(x)
+            | DataSource::Random(x)
+            | DataSource::StepPitch(x)
+            | DataSource::StepVelocity(x)
+            | DataSource::StepLength(x)
+            | DataSource::StepDensity(x)
+            | DataSource::MemoryBuffer(x)
+            | DataSource::Params(x)
+            | DataSource::Scale(x)
+            | DataSource::FullScale(x)
+            | DataSource::RandomNote(x) => x
+        }
+    }
+
     /// Clips the value between a minimum and maximum value according to the type of DataSource
     pub fn validate(&mut self) {
         let max = self.max();
+        self.inner_mut().clip(0, max);
+    }
+
+    /// Returns true if scripts can write to the source (the step sequences, the memory buffer and the params)
+    pub fn is_writable(&self) -> bool {
+        self.as_step().is_some() || matches!(self, DataSource::MemoryBuffer(_) | DataSource::Params(_))
+    }
+
+    /// Returns true if `validate` would clip the value, i.e. it can't be encoded as is
+    pub fn would_clip(&self) -> bool {
+        let limit = match self.inner() {
+            DataValue::Number(_) => self.max(),
+            DataValue::Buffer(_) => self.max().min(MEMORY_BUFFER_MAX)
+        };
+        self.inner().as_u8() > limit
+    }
+
+}
+
+impl Default for DataSource {
+    /// The constant 0
+    fn default() -> DataSource {
+        DataSource::Constant(DataValue::default())
+    }
+}
+
+impl fmt::Display for DataSource {
+    /// Formats the source as `const 36`, `step_pitch[0]`, `mem[@1]`, etc.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            DataSource::Constant(x) => x.clip(0, max),
-            DataSource::Random(x) => x.clip(0, max),
-            DataSource::StepPitch(x) => x.clip(0, max),
-            DataSource::StepVelocity(x) => x.clip(0, max),
-            DataSource::StepLength(x) => x.clip(0, max),
-            DataSource::StepDensity(x) => x.clip(0, max),
-            DataSource::MemoryBuffer(x) => x.clip(0, max),
-            DataSource::Params(x) => x.clip(0, max),
-            DataSource::Scale(x) => x.clip(0, max),
-            DataSource::FullScale(x) => x.clip(0, max),
-            DataSource::RandomNote(x) => x.clip(0, max),
+            DataSource::Constant(x) => write!(f, "const {}", x),
+            DataSource::Random(x) => write!(f, "random {}", x),
+            DataSource::StepPitch(x) => write!(f, "step_pitch[{}]", x),
+            DataSource::StepVelocity(x) => write!(f, "step_velocity[{}]", x),
+            DataSource::StepLength(x) => write!(f, "step_length[{}]", x),
+            DataSource::StepDensity(x) => write!(f, "step_density[{}]", x),
+            DataSource::MemoryBuffer(x) => write!(f, "mem[{}]", x),
+            DataSource::Params(x) => write!(f, "params[{}]", x),
+            DataSource::Scale(x) => write!(f, "scale[{}]", x),
+            DataSource::FullScale(x) => write!(f, "full_scale[{}]", x),
+            DataSource::RandomNote(x) => write!(f, "random_note {}", x)
         }
     }
+}
 
-    
+/// The kind of a DataSource, matching the opcode byte that precedes its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DataSourceKind {
+    Constant,
+    Random,
+    StepPitch,
+    StepVelocity,
+    StepLength,
+    StepDensity,
+    MemoryBuffer,
+    Params,
+    Scale,
+    FullScale,
+    RandomNote
+}
+
+impl DataSourceKind {
+    /// Every kind, in opcode order
+    pub const ALL: [DataSourceKind; 11] = [
+        DataSourceKind::Constant,
+        DataSourceKind::Random,
+        DataSourceKind::StepPitch,
+        DataSourceKind::StepVelocity,
+        DataSourceKind::StepLength,
+        DataSourceKind::StepDensity,
+        DataSourceKind::MemoryBuffer,
+        DataSourceKind::Params,
+        DataSourceKind::Scale,
+        DataSourceKind::FullScale,
+        DataSourceKind::RandomNote
+    ];
+
+    /// Returns the name used for the kind when formatting, e.g. `step_pitch` or `mem`
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            DataSourceKind::Constant => "const",
+            DataSourceKind::Random => "random",
+            DataSourceKind::StepPitch => "step_pitch",
+            DataSourceKind::StepVelocity => "step_velocity",
+            DataSourceKind::StepLength => "step_length",
+            DataSourceKind::StepDensity => "step_density",
+            DataSourceKind::MemoryBuffer => "mem",
+            DataSourceKind::Params => "params",
+            DataSourceKind::Scale => "scale",
+            DataSourceKind::FullScale => "full_scale",
+            DataSourceKind::RandomNote => "random_note"
+        }
+    }
+
+    /// Returns the human readable name of the kind, e.g. `Step Pitch`
+    pub(crate) fn title(&self) -> &'static str {
+        match self {
+            DataSourceKind::Constant => "Constant",
+            DataSourceKind::Random => "Random",
+            DataSourceKind::StepPitch => "Step Pitch",
+            DataSourceKind::StepVelocity => "Step Velocity",
+            DataSourceKind::StepLength => "Step Length",
+            DataSourceKind::StepDensity => "Step Density",
+            DataSourceKind::MemoryBuffer => "Memory Buffer",
+            DataSourceKind::Params => "Params",
+            DataSourceKind::Scale => "Scale",
+            DataSourceKind::FullScale => "Full Scale",
+            DataSourceKind::RandomNote => "Random Note"
+        }
+    }
+
+    /// Returns the opcode byte of the kind
+    pub fn code(&self) -> u8 {
+        match self {
+            DataSourceKind::Constant => 0x00,
+            DataSourceKind::Random => 0x01,
+            DataSourceKind::StepPitch => 0x02,
+            DataSourceKind::StepVelocity => 0x03,
+            DataSourceKind::StepLength => 0x04,
+            DataSourceKind::StepDensity => 0x05,
+            DataSourceKind::MemoryBuffer => 0x06,
+            DataSourceKind::Params => 0x07,
+            DataSourceKind::Scale => 0x08,
+            DataSourceKind::FullScale => 0x09,
+            DataSourceKind::RandomNote => 0x0A
+        }
+    }
+}
+
+impl TryFrom<u8> for DataSourceKind {
+    type Error = u8;
+
+    /// Decodes an opcode byte, returning the byte back if it isn't a known kind
+    fn try_from(value: u8) -> Result<DataSourceKind, u8> {
+        DataSourceKind::ALL.iter().find(|kind| kind.code() == value).copied().ok_or(value)
+    }
+}
 
+/// One of the four step sequences of a track, as returned by `DataSource::as_step`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SeqKind {
+    Pitch,
+    Velocity,
+    Length,
+    Density
 }
 
 macro_rules! data_source_fn {
-    ($name:ident, $variant:ident) => {
+    ($(#[$meta:meta])* $name:ident, $variant:ident) => {
         /// Quick method for creating a DataSource from a u8 value
+        $(#[$meta])*
         pub fn $name(value: u8) -> DataSource {
             DataSource::$variant(DataValue::from_u8(value))
         }
@@ -198,10 +558,76 @@ data_source_fn!(step_velocity, StepVelocity);
 data_source_fn!(step_length, StepLength);
 data_source_fn!(step_density, StepDensity);
 data_source_fn!(memory_buffer, MemoryBuffer);
+data_source_fn!(
+    ///
+    /// ```
+    /// use ngen_nsl::*;
+    /// // Set param 1 to the 12th note of the scale
+    /// let cmd = Commands::Set(params(0), scale(12));
+    /// assert_eq!(cmd.to_string(), "SET params[0] = scale[12]");
+    /// ```
+    params, Params
+);
+data_source_fn!(scale, Scale);
+data_source_fn!(full_scale, FullScale);
+data_source_fn!(
+    ///
+    /// ```
+    /// use ngen_nsl::*;
+    /// let cmd = Commands::Set(step_pitch(0), random_note(100));
+    /// assert_eq!(cmd.to_string(), "SET step_pitch[0] = random_note 100");
+    /// ```
+    random_note, RandomNote
+);
+
+/// Error returned by the `try_*` constructors when a value is above the maximum of its DataSource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRange {
+    pub kind: DataSourceKind,
+    pub value: u8,
+    pub max: u8,
+}
 
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is above the maximum of {} for {}", self.value, self.max, self.kind.title())
+    }
+}
+
+macro_rules! try_data_source_fn {
+    ($name:ident, $variant:ident) => {
+        /// Creates a DataSource from a number, returning an error instead of clipping values above its maximum
+        pub fn $name(value: u8) -> Result<DataSource, OutOfRange> {
+            let source = DataSource::$variant(DataValue::Number(value));
+            let max = source.max();
+            if value > max {
+                return Err(OutOfRange { kind: source.kind(), value, max });
+            }
+            Ok(source)
+        }
+    };
+}
 
+try_data_source_fn!(try_constant, Constant);
+try_data_source_fn!(try_random, Random);
+try_data_source_fn!(try_step_pitch, StepPitch);
+try_data_source_fn!(try_step_velocity, StepVelocity);
+try_data_source_fn!(try_step_length, StepLength);
+try_data_source_fn!(try_step_density, StepDensity);
+try_data_source_fn!(try_memory_buffer, MemoryBuffer);
+try_data_source_fn!(try_params, Params);
+try_data_source_fn!(try_scale, Scale);
+try_data_source_fn!(try_full_scale, FullScale);
+try_data_source_fn!(try_random_note, RandomNote);
 
-#[derive(Debug, Clone)]
+/// Two byte value used for jump targets.
+///
+/// Jump targets are encoded as a plain 16-bit big-endian byte offset (see `get_value`), which is what
+/// the decoder, `resolve_labels` and `validate_jumps` use. Bytes above 0x7F can't be sent inside a SysEx
+/// message as is though, so `is_sysex_safe` tells whether a target survives SysEx transport unchanged.
+/// Scripts shorter than 128 bytes only ever need SysEx safe targets.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Int16 {
     value_1: u8,
     value_2: u8
@@ -215,16 +641,39 @@ impl Int16 {
         }
     }
 
+    /// Returns the 16-bit big-endian value of the two bytes, the interpretation used for jump targets
     pub fn get_value(&self) -> u16 {
         (self.value_1 as u16) << 8 | self.value_2 as u16
     }
 
+    /// Returns true if both bytes are below 0x80, so the value can be sent inside a SysEx message
+    pub fn is_sysex_safe(&self) -> bool {
+        self.value_1 < 0x80 && self.value_2 < 0x80
+    }
+
+    /// Splits a 14-bit value into two 7-bit bytes so it survives SysEx transport.
+    /// Values above 0x3FFF are clamped to it instead of losing their high bits.
+    pub fn from_u14(value: u16) -> Int16 {
+        let value = value.min(0x3FFF);
+        Int16::new((value >> 7) as u8, value as u8 & 0x7F)
+    }
+
+    /// Recombines the two 7-bit bytes of a value created with `from_u14`
+    pub fn get_value_14(&self) -> u16 {
+        ((self.value_1 & 0x7F) as u16) << 7 | (self.value_2 & 0x7F) as u16
+    }
+
     pub fn code(&self) -> Vec<u8> {
         vec![self.value_1, self.value_2]
     }
 
     pub fn from_u8_vec(data: Vec<u8>) -> Int16 {
-        Int16::new(data[0], data[1])
+        Int16::from_slice(&data)
+    }
+
+    pub fn from_slice(data: &[u8]) -> Int16 {
+        // Missing bytes decode as 0
+        Int16::new(data.first().copied().unwrap_or(0), data.get(1).copied().unwrap_or(0))
     }
     
 }
@@ -232,12 +681,15 @@ impl Int16 {
 /// NSL Commands are the instructions that the NSL script will execute
 /// 
 /// Some commands require additional data (DataSource) to be passed as parameters (x and y).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Commands {
     None,
     /// Sets x to y
     Set(DataSource, DataSource),
-    /// Copies x to y
+    /// Copies y to x
+    ///
+    /// x is the destination, as with Set: NGEN writes the value of the second operand into the first.
     Copy(DataSource, DataSource),
     /// Adds y to x
     Add(DataSource, DataSource),
@@ -259,13 +711,16 @@ pub enum Commands {
     ClearMemory,
     /// Clears all tracks and memory
     ClearAll,
-    /// Selects a track
-    SelectTrack,
+    /// Selects track x as the active track
+    SelectTrack(DataSource),
+    /// Clears track x, leaving the active track unchanged
+    ClearTrackAt(DataSource),
     /// Quantizes all steps of the active track's pitch sequence
     QuantizePitch,
     /// Generates a chord progression 
     GenerateProgression,
-    /// Generates a velocity sequence to the active track usign the Euclidean algorithm
+    /// Generates a velocity sequence to the active track usign the Euclidean algorithm,
+    /// spreading x pulses (first operand) over y steps (second operand)
     GenerateEuclidean(DataSource, DataSource),
     /// Sets up a conditional statement for equality (x == y)
     CondE(DataSource, DataSource),
@@ -283,44 +738,298 @@ pub enum Commands {
     CondEnd,
     /// Ends the script
     End,
+    /// Pseudo-command marking a jump target for `JumpLabel`. Encodes to nothing (see `NSLScript::add_label`)
+    Label(String),
+    /// Pseudo-command jumping to a `Label`. Rewritten into a `Jump` by `NSLScript::resolve_labels`
+    JumpLabel(String),
+    /// Pseudo-command jumping x bytes forwards (or backwards if negative) from its own offset.
+    /// Rewritten into a `Jump` by `NSLScript::resolve_relative`
+    JumpRel(i16),
+}
+
+/// What a clear command wipes, as returned by `Commands::clear_scope`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClearScope {
+    /// The steps of a single track (ClearTrack and ClearTrackAt)
+    Track,
+    /// The memory buffer (ClearMemory)
+    Memory,
+    /// All tracks and the memory buffer (ClearAll)
+    All
+}
+
+/// Returns the encoded length in bytes (opcode included) of the command with the given opcode,
+/// or 0 if the opcode is unknown
+pub const fn opcode_len(opcode: u8) -> usize {
+    match opcode {
+        // Two operands
+        0xA1 | 0xA2 | 0xB0..=0xB3 | 0xB6 | 0xD0..=0xD5 => 5,
+        // One operand or a 16-bit address
+        0xA6 | 0xA7 | 0xC0 | 0xC2 => 3,
+        // No operands
+        0xA3..=0xA5 | 0xB4 | 0xB5 | 0xC1 | 0xD6 | 0xFF => 1,
+        _ => 0
+    }
 }
 
+/// (opcode, mnemonic, length in bytes) of every encodable command, in opcode order
+const OPCODE_TABLE: [(u8, &str, usize); 25] = [
+    (0xA1, "SET", 5),
+    (0xA2, "COPY", 5),
+    (0xA3, "CLEAR_TRACK", 1),
+    (0xA4, "CLEAR_MEM", 1),
+    (0xA5, "CLEAR_ALL", 1),
+    (0xA6, "SELECT_TRACK", 3),
+    (0xA7, "CLEAR_TRACK_AT", 3),
+    (0xB0, "ADD", 5),
+    (0xB1, "SUB", 5),
+    (0xB2, "MUL", 5),
+    (0xB3, "DIV", 5),
+    (0xB4, "QUANTIZE", 1),
+    (0xB5, "GEN_PROGRESSION", 1),
+    (0xB6, "EUCLIDEAN", 5),
+    (0xC0, "LOOP", 3),
+    (0xC1, "LOOP_END", 1),
+    (0xC2, "JMP", 3),
+    (0xD0, "COND_E", 5),
+    (0xD1, "COND_NE", 5),
+    (0xD2, "COND_GT", 5),
+    (0xD3, "COND_LT", 5),
+    (0xD4, "COND_GTE", 5),
+    (0xD5, "COND_LTE", 5),
+    (0xD6, "COND_END", 1),
+    (0xFF, "END", 1),
+];
+
+#[allow(clippy::new_without_default)]
 impl Commands {
     /// Creates a new command
     pub fn new() -> Commands {
         Commands::End
     }
 
-    /// Returns the length of the command in bytes
+    /// Creates a GenerateEuclidean command spreading `pulses` over `steps`
+    ///
+    /// ```
+    /// use ngen_nsl::*;
+    /// // x..x..x.
+    /// let cmd = Commands::euclidean(constant(3), constant(8));
+    /// assert_eq!(cmd.to_string(), "EUCLIDEAN const 3, const 8");
+    /// ```
+    pub fn euclidean(pulses: DataSource, steps: DataSource) -> Commands {
+        Commands::GenerateEuclidean(pulses, steps)
+    }
+
+    /// Returns the length of the command in bytes (see `opcode_len`)
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         match self {
-            Commands::Set(_, _) => 5,
-            Commands::Copy(_, _) => 5,
-            Commands::Add(_, _) => 5,
-            Commands::Subtract(_, _) => 5,
-            Commands::Multiply(_, _) => 5,
-            Commands::Divide(_, _) => 5,
-            Commands::GenerateEuclidean(_, _) => 5,
-            Commands::CondE(_, _) => 5,
-            Commands::CondNE(_, _) => 5,
-            Commands::CondGT(_, _) => 5,
-            Commands::CondLT(_, _) => 5,
-            Commands::CondGTE(_, _) => 5,
-            Commands::CondLTE(_, _) => 5,
-            Commands::LoopSet(_) => 3,
-            Commands::Jump(_) => 3,
-            _ => 1
-        }
-    }
-
-    /// Returns the command hex code
+            // Pseudo-commands and placeholders have no encoding
+            Commands::Label(_) | Commands::None => 0,
+            _ => opcode_len(self.opcode())
+        }
+    }
+
+    /// Returns the (x, y) operands of two-operand commands
+    pub fn operands(&self) -> Option<(&DataSource, &DataSource)> {
+        match self {
+            Commands::Set(x, y)
+            | Commands::Copy(x, y)
+            | Commands::Add(x, y)
+            | Commands::Subtract(x, y)
+            | Commands::Multiply(x, y)
+            | Commands::Divide(x, y)
+            | Commands::GenerateEuclidean(x, y)
+            | Commands::CondE(x, y)
+            | Commands::CondNE(x, y)
+            | Commands::CondGT(x, y)
+            | Commands::CondLT(x, y)
+            | Commands::CondGTE(x, y)
+            | Commands::CondLTE(x, y) => Some((x, y)),
+            _ => None
+        }
+    }
+
+    /// Returns mutable references to the (x, y) operands of two-operand commands
+    pub fn operands_mut(&mut self) -> Option<(&mut DataSource, &mut DataSource)> {
+        match self {
+            Commands::Set(x, y)
+            | Commands::Copy(x, y)
+            | Commands::Add(x, y)
+            | Commands::Subtract(x, y)
+            | Commands::Multiply(x, y)
+            | Commands::Divide(x, y)
+            | Commands::GenerateEuclidean(x, y)
+            | Commands::CondE(x, y)
+            | Commands::CondNE(x, y)
+            | Commands::CondGT(x, y)
+            | Commands::CondLT(x, y)
+            | Commands::CondGTE(x, y)
+            | Commands::CondLTE(x, y) => Some((x, y)),
+            _ => None
+        }
+    }
+
+    /// Returns the same command with new (x, y) operands, or None if it isn't a two-operand command
+    pub fn with_operands(&self, x: DataSource, y: DataSource) -> Option<Commands> {
+        let mut command = self.clone();
+        let (old_x, old_y) = command.operands_mut()?;
+        *old_x = x;
+        *old_y = y;
+        Some(command)
+    }
+
+    /// Returns the operand of single-operand commands (LoopSet, SelectTrack and ClearTrackAt)
+    pub fn single_operand(&self) -> Option<&DataSource> {
+        match self {
+            Commands::LoopSet(x) | Commands::SelectTrack(x) | Commands::ClearTrackAt(x) => Some(x),
+            _ => None
+        }
+    }
+
+    /// Returns a mutable reference to the operand of single-operand commands
+    pub fn single_operand_mut(&mut self) -> Option<&mut DataSource> {
+        match self {
+            Commands::LoopSet(x) | Commands::SelectTrack(x) | Commands::ClearTrackAt(x) => Some(x),
+            _ => None
+        }
+    }
+
+    /// Returns true for the conditionals and the CondEnd that closes them
+    pub fn is_conditional(&self) -> bool {
+        matches!(self,
+            Commands::CondE(_, _)
+            | Commands::CondNE(_, _)
+            | Commands::CondGT(_, _)
+            | Commands::CondLT(_, _)
+            | Commands::CondGTE(_, _)
+            | Commands::CondLTE(_, _)
+            | Commands::CondEnd)
+    }
+
+    /// Returns true for LoopSet and LoopEnd
+    pub fn is_loop_control(&self) -> bool {
+        matches!(self, Commands::LoopSet(_) | Commands::LoopEnd)
+    }
+
+    /// Returns true for Add, Subtract, Multiply and Divide
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(self, Commands::Add(_, _) | Commands::Subtract(_, _) | Commands::Multiply(_, _) | Commands::Divide(_, _))
+    }
+
+    /// Returns true for the End command, which stops the script
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, Commands::End)
+    }
+
+    /// Returns true for ClearTrack, ClearTrackAt, ClearMemory and ClearAll
+    pub fn is_clear(&self) -> bool {
+        self.clear_scope().is_some()
+    }
+
+    /// Returns what the command clears, or None if it isn't a clear command
+    pub fn clear_scope(&self) -> Option<ClearScope> {
+        match self {
+            Commands::ClearTrack | Commands::ClearTrackAt(_) => Some(ClearScope::Track),
+            Commands::ClearMemory => Some(ClearScope::Memory),
+            Commands::ClearAll => Some(ClearScope::All),
+            _ => None
+        }
+    }
+
+    /// Returns the short name of the command, e.g. `SET` or `COND_GT`, as used by `Display` and the assembler
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Commands::None => "NOP",
+            Commands::Set(_, _) => "SET",
+            Commands::Copy(_, _) => "COPY",
+            Commands::ClearTrack => "CLEAR_TRACK",
+            Commands::ClearMemory => "CLEAR_MEM",
+            Commands::ClearAll => "CLEAR_ALL",
+            Commands::SelectTrack(_) => "SELECT_TRACK",
+            Commands::ClearTrackAt(_) => "CLEAR_TRACK_AT",
+            Commands::Add(_, _) => "ADD",
+            Commands::Subtract(_, _) => "SUB",
+            Commands::Multiply(_, _) => "MUL",
+            Commands::Divide(_, _) => "DIV",
+            Commands::QuantizePitch => "QUANTIZE",
+            Commands::GenerateProgression => "GEN_PROGRESSION",
+            Commands::GenerateEuclidean(_, _) => "EUCLIDEAN",
+            Commands::CondE(_, _) => "COND_E",
+            Commands::CondNE(_, _) => "COND_NE",
+            Commands::CondGT(_, _) => "COND_GT",
+            Commands::CondLT(_, _) => "COND_LT",
+            Commands::CondGTE(_, _) => "COND_GTE",
+            Commands::CondLTE(_, _) => "COND_LTE",
+            Commands::CondEnd => "COND_END",
+            Commands::LoopSet(_) => "LOOP",
+            Commands::LoopEnd => "LOOP_END",
+            Commands::Jump(_) | Commands::JumpLabel(_) | Commands::JumpRel(_) => "JMP",
+            Commands::End => "END",
+            Commands::Label(_) => "LABEL"
+        }
+    }
+
+    /// Returns (opcode, mnemonic, length in bytes) for every encodable command, in opcode order.
+    /// `Jump` and `JumpRel` share the `JMP` opcode.
+    pub fn all_opcodes() -> &'static [(u8, &'static str, usize)] {
+        &OPCODE_TABLE
+    }
+
+    /// Creates the command with the given mnemonic (case insensitive) and all operands set to 0.
+    /// `JMP` creates a `Jump` and `LABEL` a `Label` with an empty name.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Commands> {
+        let zero = || DataSource::Constant(DataValue::Number(0));
+        let command = match mnemonic.to_ascii_uppercase().as_str() {
+            "NOP" => Commands::None,
+            "SET" => Commands::Set(zero(), zero()),
+            "COPY" => Commands::Copy(zero(), zero()),
+            "CLEAR_TRACK" => Commands::ClearTrack,
+            "CLEAR_MEM" => Commands::ClearMemory,
+            "CLEAR_ALL" => Commands::ClearAll,
+            "SELECT_TRACK" => Commands::SelectTrack(zero()),
+            "CLEAR_TRACK_AT" => Commands::ClearTrackAt(zero()),
+            "ADD" => Commands::Add(zero(), zero()),
+            "SUB" => Commands::Subtract(zero(), zero()),
+            "MUL" => Commands::Multiply(zero(), zero()),
+            "DIV" => Commands::Divide(zero(), zero()),
+            "QUANTIZE" => Commands::QuantizePitch,
+            "GEN_PROGRESSION" => Commands::GenerateProgression,
+            "EUCLIDEAN" => Commands::GenerateEuclidean(zero(), zero()),
+            "COND_E" => Commands::CondE(zero(), zero()),
+            "COND_NE" => Commands::CondNE(zero(), zero()),
+            "COND_GT" => Commands::CondGT(zero(), zero()),
+            "COND_LT" => Commands::CondLT(zero(), zero()),
+            "COND_GTE" => Commands::CondGTE(zero(), zero()),
+            "COND_LTE" => Commands::CondLTE(zero(), zero()),
+            "COND_END" => Commands::CondEnd,
+            "LOOP" => Commands::LoopSet(zero()),
+            "LOOP_END" => Commands::LoopEnd,
+            "JMP" => Commands::Jump(Int16::new(0, 0)),
+            "END" => Commands::End,
+            "LABEL" => Commands::Label(String::new()),
+            _ => return None
+        };
+        Some(command)
+    }
+
+    /// Returns the command hex code, same as `opcode`
     pub fn cmd_code(&self) -> u8 {
+        self.opcode()
+    }
+
+    /// Returns the opcode, the single leading byte of the encoded command.
+    /// The `None` and `Label` placeholders encode to nothing and return 0x00.
+    pub fn opcode(&self) -> u8 {
         match self {
             Commands::Set(_, _) => 0xA1,
             Commands::Copy(_, _) => 0xA2,
             Commands::ClearTrack => 0xA3,
             Commands::ClearMemory => 0xA4,
             Commands::ClearAll => 0xA5,
+            Commands::SelectTrack(_) => 0xA6,
+            Commands::ClearTrackAt(_) => 0xA7,
             Commands::Add(_, _) => 0xB0,
             Commands::Subtract(_, _) => 0xB1,
             Commands::Multiply(_, _) => 0xB2,
@@ -338,15 +1047,23 @@ impl Commands {
             Commands::LoopSet(_) => 0xC0,
             Commands::LoopEnd => 0xC1,
             Commands::Jump(_) => 0xC2,
+            Commands::JumpLabel(_) => 0xC2,
+            Commands::JumpRel(_) => 0xC2,
             Commands::End => 0xFF,
             _ => 0x00
         }
     }
 
-    /// Encodes the command into a `Vec<u8>` value
-    pub fn code(&mut self) -> Vec<u8> {
+    /// Encodes the command into a `Vec<u8>` value.
+    /// JumpLabel and JumpRel are encoded as a jump to 0x0000 until they are resolved
+    /// (see `NSLScript::resolve_labels` and `NSLScript::resolve_relative`).
+    pub fn code(&self) -> Vec<u8> {
         info!("Converting command to Vec<u8>: {:?}", self);
-        let mut code: Vec<u8> = vec![self.cmd_code()];
+        // Placeholders have no encoding, a 0x00 byte would desync the decoder
+        if let Commands::Label(_) | Commands::None = self {
+            return Vec::new();
+        }
+        let mut code: Vec<u8> = vec![self.opcode()];
         match self {
             Commands::Set(x, y) => {
                 code.extend(x.code());
@@ -378,6 +1095,20 @@ impl Commands {
             Commands::Jump(x) => {
                 code.extend(x.code());
             },
+            Commands::JumpLabel(name) => {
+                debug!("Encoding unresolved label {} as a jump to 0x0000", name);
+                code.extend([0x00, 0x00]);
+            },
+            Commands::JumpRel(x) => {
+                debug!("Encoding unresolved relative jump {:+} as a jump to 0x0000", x);
+                code.extend([0x00, 0x00]);
+            },
+            Commands::SelectTrack(x) => {
+                code.extend(x.code());
+            },
+            Commands::ClearTrackAt(x) => {
+                code.extend(x.code());
+            },
             Commands::GenerateEuclidean(x, y) => {
                 code.extend(x.code());
                 code.extend(y.code());
@@ -411,6 +1142,8 @@ impl Commands {
             }
         }
         debug!("> Converted command to Vec<u8>: {:?} > {:?}", self, code);
+        // Catches commands whose encoding and len() got out of sync
+        debug_assert_eq!(code.len(), self.len(), "encoded length of {:?} doesn't match len()", self);
         code
     }
 
@@ -422,6 +1155,8 @@ impl Commands {
             0xA3 => Commands::ClearTrack,
             0xA4 => Commands::ClearMemory,
             0xA5 => Commands::ClearAll,
+            0xA6 => Commands::SelectTrack(DataSource::Constant(DataValue::from_u8(0))),
+            0xA7 => Commands::ClearTrackAt(DataSource::Constant(DataValue::from_u8(0))),
             0xB0 => Commands::Add(DataSource::Constant(DataValue::from_u8(0)), DataSource::Constant(DataValue::from_u8(0))),
             0xB1 => Commands::Subtract(DataSource::Constant(DataValue::from_u8(0)), DataSource::Constant(DataValue::from_u8(0))),
             0xB2 => Commands::Multiply(DataSource::Constant(DataValue::from_u8(0)), DataSource::Constant(DataValue::from_u8(0))),
@@ -446,11 +1181,23 @@ impl Commands {
 
     /// Decodes a `Vec<u8>` value into a Command
     pub fn from_u8_vec(data: Vec<u8>) -> Commands {
-        let mut cmd = Commands::from_u8(data[0]);
+        Commands::from_slice(&data)
+    }
+
+    /// Decodes the bytes of a single command
+    pub fn from_slice(data: &[u8]) -> Commands {
+        let mut cmd = match data.first() {
+            Some(opcode) => Commands::from_u8(*opcode),
+            None => return Commands::None
+        };
+        if data.len() < cmd.len() {
+            debug!("Not enough data for command: {:?} (Len: {})", data, cmd.len());
+            return Commands::None;
+        }
         cmd = match data.len() {
             5 => {
-                let x = DataSource::from_u8_vec(data[1..3].to_vec());
-                let y = DataSource::from_u8_vec(data[3..5].to_vec());
+                let x = DataSource::from_slice(&data[1..3]);
+                let y = DataSource::from_slice(&data[3..5]);
                 match cmd {
                     Commands::Set(_, _) => Commands::Set(x, y),
                     Commands::Copy(_, _) => Commands::Copy(x, y),
@@ -469,9 +1216,12 @@ impl Commands {
                 }
             },
             3 => {
-                let x = DataSource::from_u8_vec(data[1..3].to_vec());
+                let x = DataSource::from_slice(&data[1..3]);
                 match cmd {
                     Commands::LoopSet(_) => Commands::LoopSet(x),
+                    Commands::SelectTrack(_) => Commands::SelectTrack(x),
+                    Commands::ClearTrackAt(_) => Commands::ClearTrackAt(x),
+                    Commands::Jump(_) => Commands::Jump(Int16::from_slice(&data[1..3])),
                     _ => Commands::None
                 }
             },
@@ -487,27 +1237,256 @@ impl Commands {
 
 }
 
+impl fmt::Display for Commands {
+    /// Formats the command as a mnemonic followed by its operands, e.g. `SET step_pitch[0] = const 36`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Commands::None => write!(f, "NOP"),
+            Commands::Set(x, y) => write!(f, "SET {} = {}", x, y),
+            Commands::Copy(x, y) => write!(f, "COPY {} <- {}", x, y),
+            Commands::Add(x, y) => write!(f, "ADD {} + {}", x, y),
+            Commands::Subtract(x, y) => write!(f, "SUB {} - {}", x, y),
+            Commands::Multiply(x, y) => write!(f, "MUL {} * {}", x, y),
+            Commands::Divide(x, y) => write!(f, "DIV {} / {}", x, y),
+            Commands::LoopSet(x) => write!(f, "LOOP {}", x),
+            Commands::LoopEnd => write!(f, "LOOP_END"),
+            Commands::Jump(x) => write!(f, "JMP 0x{:04X}", x.get_value()),
+            Commands::ClearTrack => write!(f, "CLEAR_TRACK"),
+            Commands::ClearMemory => write!(f, "CLEAR_MEM"),
+            Commands::ClearAll => write!(f, "CLEAR_ALL"),
+            Commands::SelectTrack(x) => write!(f, "SELECT_TRACK {}", x),
+            Commands::ClearTrackAt(x) => write!(f, "CLEAR_TRACK_AT {}", x),
+            Commands::QuantizePitch => write!(f, "QUANTIZE"),
+            Commands::GenerateProgression => write!(f, "GEN_PROGRESSION"),
+            Commands::GenerateEuclidean(x, y) => write!(f, "EUCLIDEAN {}, {}", x, y),
+            Commands::CondE(x, y) => write!(f, "COND_E {} == {}", x, y),
+            Commands::CondNE(x, y) => write!(f, "COND_NE {} != {}", x, y),
+            Commands::CondGT(x, y) => write!(f, "COND_GT {} > {}", x, y),
+            Commands::CondLT(x, y) => write!(f, "COND_LT {} < {}", x, y),
+            Commands::CondGTE(x, y) => write!(f, "COND_GTE {} >= {}", x, y),
+            Commands::CondLTE(x, y) => write!(f, "COND_LTE {} <= {}", x, y),
+            Commands::CondEnd => write!(f, "COND_END"),
+            Commands::End => write!(f, "END"),
+            Commands::Label(name) => write!(f, "{}:", name),
+            Commands::JumpLabel(name) => write!(f, "JMP {}", name),
+            Commands::JumpRel(x) => write!(f, "JMP {:+}", x)
+        }
+    }
+}
+
+/// Escapes a string so it can be used inside a JSON string literal
+pub(crate) fn json_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+/// Version byte written after the "NSL" magic bytes by this crate
+pub const NSL_VERSION: u8 = 0x01;
+
+/// Header versions that can be decoded
+const SUPPORTED_VERSIONS: &[u8] = &[NSL_VERSION];
+
+/// Size of the script memory on NGEN in bytes (header included)
+pub const NGEN_MAX_SCRIPT_BYTES: usize = 1024;
+
+/// Errors that can occur while encoding or decoding an NSL script
+#[derive(Debug)]
+pub enum NslError {
+    /// The data doesn't start with the "NSL" magic bytes
+    BadMagic,
+    /// The data ended before the item starting at `at` was complete (`needed` is its full length in bytes)
+    UnexpectedEof { at: usize, needed: usize },
+    /// The byte at `at` is not a known command opcode
+    UnknownOpcode { at: usize, byte: u8 },
+    /// The encoded script is `size` bytes, more than the `max` bytes allowed
+    ScriptTooLarge { size: usize, max: usize },
+    /// The checksum stored after the commands doesn't match the checksum computed from them
+    ChecksumMismatch { expected: u8, found: u8 },
+    /// The header declares a format version this crate can't decode
+    UnsupportedVersion { version: u8 },
+    /// The text passed to `from_hex_str` contains something that isn't a hex byte
+    InvalidHex { token: String },
+    /// A value is above the maximum of its DataSource
+    OutOfRange(OutOfRange),
+    /// The loop or conditional opened or closed by the command at `index` has no matching counterpart
+    UnbalancedControl { index: usize },
+    /// The byte at `at` breaks the SysEx framing: a missing start or end byte, or a data byte above 0x7F
+    InvalidSysEx { at: usize },
+    /// A ScriptBank holds `count` scripts, more than the `max` it can encode
+    TooManyScripts { count: usize, max: usize },
+    /// `len` bytes are left over at offset `at` after the last item of the data
+    TrailingData { at: usize, len: usize },
+    /// The JumpLabel or JumpRel at `index` would be encoded as a jump to 0x0000 (see `resolve_labels`)
+    UnresolvedJump { index: usize },
+    /// Reading the script failed
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+impl fmt::Display for NslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NslError::BadMagic => write!(f, "data doesn't start with the NSL header"),
+            NslError::UnexpectedEof { at, needed } => write!(f, "data ended before the {} bytes starting at offset {}", needed, at),
+            NslError::UnknownOpcode { at, byte } => write!(f, "unknown opcode 0x{:02X} at offset {}", byte, at),
+            NslError::ScriptTooLarge { size, max } => write!(f, "script is {} bytes, more than the {} bytes allowed", size, max),
+            NslError::ChecksumMismatch { expected, found } => write!(f, "checksum mismatch: expected 0x{:02X}, found 0x{:02X}", expected, found),
+            NslError::UnsupportedVersion { version } => write!(f, "unsupported NSL version {}", version),
+            NslError::InvalidHex { token } => write!(f, "invalid hex byte \"{}\"", token),
+            NslError::OutOfRange(error) => write!(f, "{}", error),
+            NslError::UnbalancedControl { index } => write!(f, "unbalanced loop or conditional at command {}", index),
+            NslError::InvalidSysEx { at } => write!(f, "invalid SysEx byte at offset {}", at),
+            NslError::UnresolvedJump { index } => write!(f, "unresolved jump at command {}", index),
+            NslError::TooManyScripts { count, max } => write!(f, "bank holds {} scripts, more than the {} allowed", count, max),
+            NslError::TrailingData { at, len } => write!(f, "{} unexpected bytes at offset {}", len, at),
+            #[cfg(feature = "std")]
+            NslError::Io(error) => write!(f, "I/O error: {}", error),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NslError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NslError::Io(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for NslError {
+    fn from(error: std::io::Error) -> NslError {
+        NslError::Io(error)
+    }
+}
+
+impl From<OutOfRange> for NslError {
+    fn from(error: OutOfRange) -> NslError {
+        NslError::OutOfRange(error)
+    }
+}
+
+impl Default for NSLScript {
+    /// An empty script, same as `NSLScript::new`
+    fn default() -> NSLScript {
+        NSLScript::new()
+    }
+}
+
+impl TryFrom<&[u8]> for NSLScript {
+    type Error = NslError;
+
+    /// Decodes a byte slice, same as `NSLScript::try_from_u8_vec`
+    fn try_from(data: &[u8]) -> Result<NSLScript, NslError> {
+        NSLScript::try_from_u8_vec(data)
+    }
+}
+
+/// Options for `NSLScript::from_u8_vec_with`. The default rejects unknown opcodes like `try_from_u8_vec`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Skip commands with an unknown opcode instead of failing with `NslError::UnknownOpcode`
+    pub skip_unknown: bool,
+    /// Number of bytes (opcode included) to skip for every unknown command. Defaults to 1
+    pub assume_len: Option<usize>,
+}
+
+/// Bytes skipped by `NSLScript::decode_recovering` because they didn't decode into a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JunkRegion {
+    /// Offset of the first skipped byte
+    pub at: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// XOR checksum of the command bytes of a script
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum ^ byte)
+}
+
+/// A command flattened into its opcode and the (kind, value) byte pairs of its operands.
+/// Jump stores its address as a single (high, low) pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommandRow {
+    pub opcode: u8,
+    pub operands: Vec<(u8, u8)>
+}
+
 /// NSLScript is the main structure used for creating and manipulating NSL scripts.
 /// 
 /// It can encode and decode NSL scripts into a `Vec<u8>` value.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NSLScript {
-    pub commands: Vec<Commands>
+    pub commands: Vec<Commands>,
+    /// Name and description of the script. Never encoded, see `export_with_meta`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub metadata: Option<ScriptMeta>,
+    #[cfg_attr(feature = "serde", serde(default = "default_version"))]
+    version: u8
+}
+
+#[cfg(feature = "serde")]
+fn default_version() -> u8 {
+    NSL_VERSION
 }
 
 impl NSLScript {
     // Creates a new NSLScript with no commands
     pub fn new() -> NSLScript {
+        NSLScript::with_version(NSL_VERSION)
+    }
+
+    // Creates a new NSLScript with no commands that is encoded with the given header version.
+    // Only the versions in SUPPORTED_VERSIONS can be decoded again.
+    pub fn with_version(version: u8) -> NSLScript {
         NSLScript {
-            commands: Vec::new()
+            commands: Vec::new(),
+            metadata: None,
+            version
         }
     }
 
+    // Returns the format version written in the header
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
     // Add a command to the script
     pub fn add_command(&mut self, command: Commands) {
         self.commands.push(command);
     }
 
+    // Insert a command before the command at `index` (or at the end if `index` is the command count).
+    // Returns false and leaves the script unchanged if `index` is past the end.
+    // Absolute jumps become stale when commands move: use labels and run `resolve_labels` after editing,
+    // or check the script with `validate_jumps`.
+    pub fn insert_command(&mut self, index: usize, command: Commands) -> bool {
+        if index > self.commands.len() {
+            return false;
+        }
+        self.commands.insert(index, command);
+        true
+    }
+
+    // Remove and return the command at `index`, or None if there is no command there.
+    // Like `insert_command`, this can leave absolute jumps pointing at the wrong command.
+    pub fn remove_command(&mut self, index: usize) -> Option<Commands> {
+        (index < self.commands.len()).then(|| self.commands.remove(index))
+    }
+
     // Add multiple commands to the script
     pub fn add_commands(&mut self, commands: Vec<Commands>) {
         for command in commands {
@@ -515,85 +1494,1639 @@ impl NSLScript {
         }
     }
 
-    // Encode the script into a Vec<u8> value
-    pub fn code(&mut self) -> Vec<u8> {
+    // Encode the script into a Vec<u8> value.
+    // Operands aren't clipped to the range of their source (see `DataSource::code`), call `normalize` first.
+    // JumpLabel and JumpRel are encoded as a jump to 0x0000 (into the header) until `resolve_labels` and
+    // `resolve_relative` have run; `code_checked` refuses to encode them.
+    pub fn code(&self) -> Vec<u8> {
+        // The final size is known up front, so the buffer never has to grow
+        let mut code: Vec<u8> = Vec::with_capacity(self.len_bytes());
+        self.encode_into(&mut code);
+        code
+    }
+
+    // Encode the script into `out`, replacing its contents.
+    // Reusing the same buffer avoids an allocation every time a script is re-encoded (e.g. for a live preview).
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        out.clear();
+        out.reserve(self.len_bytes());
         // Add the NSL header
-        let mut code: Vec<u8> = vec![0x4E, 0x53, 0x4C, 0x01];
-        for command in &mut self.commands {
+        out.extend([0x4E, 0x53, 0x4C, self.version]);
+        for command in &self.commands {
+            if let Commands::None = command {
+                debug!("Skipping placeholder command {:?}", command);
+                continue;
+            }
             let cmd_code = command.code();
             debug!("Command: {:?} > {:?}", command, cmd_code);
-            code.extend(cmd_code);
+            out.extend(cmd_code);
 
-            debug!("Code: {:?}", code);
+            debug!("Code: {:?}", out);
         }
-        code
     }
 
+    // Decode a Vec<u8> value into a NSLScript, returning None if the data is invalid
     pub fn from_u8_vec(data: Vec<u8>) -> Option<NSLScript> {
-        info!("Converting u8 data to NSLScript");
-        info!("Data size: {:?}", data.len());
+        NSLScript::from_slice(&data)
+    }
+
+    // Decode a byte slice into a NSLScript without copying it, returning None if the data is invalid
+    pub fn from_slice(data: &[u8]) -> Option<NSLScript> {
+        NSLScript::try_from_u8_vec(data).ok()
+    }
+
+    // Encode the script, decode it back and check that nothing was lost on the way.
+    // Values aren't clipped to the range of their source, so out of range values only round-trip after `normalize`.
+    // Neither Label nor JumpLabel round-trips (see resolve_labels): a Label has no encoding, and a
+    // JumpLabel encodes as the unresolved jump C2 00 00, which decodes back as a Jump.
+    // To use it as a property with proptest or quickcheck, generate a Vec<Commands> and assert
+    // `roundtrip_ok()` on a script holding them for every generated value.
+    pub fn roundtrip_ok(&self) -> bool {
+        let code = self.code();
+        match NSLScript::try_from_u8_vec(&code) {
+            // Metadata is never encoded
+            Ok(decoded) => decoded.commands == self.commands && decoded.version == self.version,
+            Err(_) => false
+        }
+    }
+
+    // Decode a byte slice into a NSLScript, reporting why the data is invalid
+    pub fn try_from_u8_vec(data: &[u8]) -> Result<NSLScript, NslError> {
+        NSLScript::decode(data, false, &DecodeOptions::default()).map(|(script, _)| script)
+    }
+
+    // Decode a script from hex bytes separated by whitespace or commas, e.g. "4E 53 4C 01 FF" or "0x4E,0x53,..."
+    pub fn from_hex_str(text: &str) -> Result<NSLScript, NslError> {
+        let mut data = Vec::new();
+        for token in text.split(|c: char| c.is_whitespace() || c == ',').filter(|token| !token.is_empty()) {
+            let digits = token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")).unwrap_or(token);
+            match u8::from_str_radix(digits, 16) {
+                Ok(byte) if !digits.starts_with('+') => data.push(byte),
+                _ => return Err(NslError::InvalidHex { token: String::from(token) })
+            }
+        }
+        NSLScript::try_from_u8_vec(&data)
+    }
+
+    // Encode the script as space separated uppercase hex bytes
+    pub fn to_hex_str(&self) -> String {
+        let bytes: Vec<String> = self.code().iter().map(|byte| format!("{:02X}", byte)).collect();
+        bytes.join(" ")
+    }
+
+    // Decode a byte slice up to and including the first End command.
+    // Returns the script and the number of bytes consumed, so trailing bytes after End can be detected.
+    pub fn from_u8_vec_strict(data: &[u8]) -> Result<(NSLScript, usize), NslError> {
+        NSLScript::decode(data, true, &DecodeOptions::default())
+    }
+
+    // Decode several End-terminated scripts stored back to back after a single header.
+    // Every script gets the version of the header. Commands after the last End form a final,
+    // unterminated script; nothing after the last End gives no extra script.
+    pub fn split_at_ends(data: &[u8]) -> Result<Vec<NSLScript>, NslError> {
+        let version = NSLScript::check_header(data)?;
+        let mut scripts = Vec::new();
+        let mut commands = Vec::new();
+        for command in CommandCursor::new(data)? {
+            let command = command?;
+            let end = command.is_terminator();
+            commands.push(command);
+            if end {
+                scripts.push(NSLScript { commands: core::mem::take(&mut commands), metadata: None, version });
+            }
+        }
+        if !commands.is_empty() {
+            scripts.push(NSLScript { commands, metadata: None, version });
+        }
+        Ok(scripts)
+    }
+
+    // Decode a byte slice with options for opcodes this crate doesn't know (see DecodeOptions)
+    pub fn from_u8_vec_with(data: &[u8], options: DecodeOptions) -> Result<NSLScript, NslError> {
+        NSLScript::decode(data, false, &options).map(|(script, _)| script)
+    }
+
+    // Decode a possibly corrupt script, salvaging every command that can be decoded.
+    // Bytes that aren't a known opcode (and a command cut off by the end of the data) are skipped up to
+    // the next known opcode and reported as junk. Only a bad header is an error.
+    pub fn decode_recovering(data: &[u8]) -> Result<(NSLScript, Vec<JunkRegion>), NslError> {
+        let version = NSLScript::check_header(data)?;
+        let mut commands = Vec::new();
+        let mut junk: Vec<JunkRegion> = Vec::new();
+        let mut i = 4;
+        while i < data.len() {
+            let len = opcode_len(data[i]);
+            if len != 0 && i + len <= data.len() {
+                commands.push(Commands::from_slice(&data[i..i + len]));
+                i += len;
+                continue;
+            }
+            debug!("Skipping junk byte {:#04x} at {}", data[i], i);
+            match junk.last_mut() {
+                Some(region) if region.at + region.bytes.len() == i => region.bytes.push(data[i]),
+                _ => junk.push(JunkRegion { at: i, bytes: vec![data[i]] })
+            }
+            i += 1;
+        }
+        Ok((NSLScript { commands, metadata: None, version }, junk))
+    }
+
+    fn decode(data: &[u8], stop_at_end: bool, options: &DecodeOptions) -> Result<(NSLScript, usize), NslError> {
+        info!("Converting u8 data to NSLScript");
+        info!("Data size: {:?}", data.len());
         let mut cmds: Vec<Commands> = Vec::new();
         let mut i = 0;
-        // Match first 3 characters to "NSL"
-        if data[0] != 0x4E || data[1] != 0x53 || data[2] != 0x4C {
-            return None;
-        }
+        let version = NSLScript::check_header(data)?;
         i += 4;
         while i < data.len() {
             debug!("----------------");
             debug!("Index: {}", i);
-            let mut cmd = Commands::from_u8(data[i]);
-            let len = cmd.len();
-            debug!("Matching command {:#04x}: {:?} (Len: {})", data[i], cmd, len);
-            let cmd_data = data[i..i+len].to_vec();
-            cmd = Commands::from_u8_vec(cmd_data);
+            let len = opcode_len(data[i]);
+            if len == 0 {
+                if !options.skip_unknown {
+                    return Err(NslError::UnknownOpcode { at: i, byte: data[i] });
+                }
+                let skip = options.assume_len.unwrap_or(1).max(1);
+                debug!("Skipping {} bytes of unknown command {:#04x}", skip, data[i]);
+                i += skip;
+                continue;
+            }
+            debug!("Matching command {:#04x} (Len: {})", data[i], len);
+            if i + len > data.len() {
+                return Err(NslError::UnexpectedEof { at: i, needed: len });
+            }
+            let cmd = Commands::from_slice(&data[i..i+len]);
             info!("Converted step {}: {:?}", i, cmd);
+            i += len;
+            if stop_at_end && matches!(cmd, Commands::End) {
+                cmds.push(cmd);
+                debug!("Stopping at End, {} trailing bytes", data.len() - i);
+                break;
+            }
             cmds.push(cmd);
+        }
+        Ok((NSLScript {
+            commands: cmds,
+            metadata: None,
+            version
+        }, i))
+    }
+
+    // Count the commands of an encoded script without decoding them, failing like `try_from_u8_vec` would
+    pub fn count_commands(data: &[u8]) -> Result<usize, NslError> {
+        NSLScript::check_header(data)?;
+        let mut count = 0;
+        let mut i = 4;
+        while i < data.len() {
+            let len = opcode_len(data[i]);
+            if len == 0 {
+                return Err(NslError::UnknownOpcode { at: i, byte: data[i] });
+            }
+            if i + len > data.len() {
+                return Err(NslError::UnexpectedEof { at: i, needed: len });
+            }
+            i += len;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Return the index of the first End command, if the script has one.
+    // A well-formed script has exactly one End, as its last command (see `validate_warnings`).
+    pub fn end_position(&self) -> Option<usize> {
+        self.commands.iter().position(|command| matches!(command, Commands::End))
+    }
+
+    // Append the commands of another script, dropping the trailing End of this one.
+    // Jumps in the other script are relocated by the size of this script's commands so they
+    // keep pointing at the same commands. Labels are kept as they are.
+    // Fails without changing the script if a relocated jump lands past 0xFFFF.
+    pub fn append(&mut self, other: &NSLScript) -> Result<(), LabelError> {
+        let kept = match self.commands.last() {
+            Some(Commands::End) => self.commands.len() - 1,
+            _ => self.commands.len()
+        };
+        let shift: usize = self.commands[..kept].iter().map(|command| command.len()).sum();
+        let mut commands: Vec<Commands> = Vec::with_capacity(other.commands.len());
+        for (index, command) in other.commands.iter().enumerate() {
+            commands.push(match command {
+                Commands::Jump(x) => {
+                    let target = x.get_value() as usize + shift;
+                    let [hi, lo] = u16::try_from(target)
+                        .map_err(|_| LabelError::JumpOutOfRange { index, target })?
+                        .to_be_bytes();
+                    Commands::Jump(Int16::new(hi, lo))
+                },
+                _ => command.clone()
+            });
+        }
+        self.commands.truncate(kept);
+        self.commands.extend(commands);
+        Ok(())
+    }
+
+    // Canonicalize the script so logically equal scripts compare (and encode) equal:
+    // every operand is clipped to its valid range and None placeholders are removed
+    pub fn normalize(&mut self) {
+        self.commands.retain(|command| !matches!(command, Commands::None));
+        self.map_sources(DataSource::validate);
+    }
+
+    // Apply `f` to every DataSource operand of the script, in script order (x before y).
+    // Jump addresses aren't DataSources and are left untouched.
+    pub fn map_sources<F: FnMut(&mut DataSource)>(&mut self, mut f: F) {
+        for command in &mut self.commands {
+            if let Some((x, y)) = command.operands_mut() {
+                f(x);
+                f(y);
+            } else if let Some(x) = command.single_operand_mut() {
+                f(x);
+            }
+        }
+    }
+
+    // Shift every pitch constant by `semitones`, clamped to the range of a constant.
+    // A constant counts as a pitch when it's the source of a Set or Add on a StepPitch destination;
+    // pitches read from scales, memory or params are left untouched. An Add is only shifted when
+    // the last Set of the same step, in script order, didn't already set it from a constant,
+    // so each note moves once: `Set(pitch, scale) + Add(pitch, 48)` shifts the Add,
+    // `Set(pitch, 36) + Add(pitch, 12)` only shifts the Set.
+    pub fn transpose(&mut self, semitones: i8) {
+        let mut shifted: Vec<DataSource> = Vec::new();
+        for command in &mut self.commands {
+            let source = match command {
+                Commands::Set(step @ DataSource::StepPitch(_), source) => {
+                    let constant = matches!(source, DataSource::Constant(_));
+                    match shifted.iter().position(|shifted| shifted == step) {
+                        Some(index) if !constant => { shifted.remove(index); },
+                        None if constant => shifted.push(step.clone()),
+                        _ => {}
+                    }
+                    source
+                },
+                Commands::Add(step @ DataSource::StepPitch(_), source) if !shifted.contains(step) => source,
+                _ => continue
+            };
+            if let DataSource::Constant(_) = source {
+                let max = source.max() as i16;
+                if let DataValue::Number(x) = source.inner_mut() {
+                    *x = (*x as i16 + semitones as i16).clamp(0, max) as u8;
+                }
+            }
+        }
+    }
+
+    // Multiply every velocity constant by `factor`, rounded and clamped to the range of a constant.
+    // A constant counts as a velocity when it's the source of a Set on a StepVelocity destination.
+    pub fn scale_velocity(&mut self, factor: f32) {
+        for command in &mut self.commands {
+            if let Commands::Set(DataSource::StepVelocity(_), source @ DataSource::Constant(_)) = command {
+                let max = source.max();
+                if let DataValue::Number(x) = source.inner_mut() {
+                    // `as` saturates, so negative factors give 0
+                    *x = ((*x as f32 * factor + 0.5) as u8).min(max);
+                }
+            }
+        }
+    }
+
+    // Flatten the script into one CommandRow per command, using the encoded bytes.
+    // Label pseudo-commands have no encoding and are skipped.
+    pub fn to_rows(&self) -> Vec<CommandRow> {
+        let mut rows = Vec::new();
+        for command in &self.commands {
+            let code = command.code();
+            if let Some((opcode, operands)) = code.split_first() {
+                rows.push(CommandRow {
+                    opcode: *opcode,
+                    operands: operands.chunks(2).map(|pair| (pair[0], pair[1])).collect()
+                });
+            }
+        }
+        rows
+    }
+
+    // Rebuild a script from rows created by to_rows, returning None if a row has an unknown
+    // opcode or the wrong number of operands for its command
+    pub fn from_rows(rows: &[CommandRow]) -> Option<NSLScript> {
+        let mut script = NSLScript::new();
+        for row in rows {
+            let template = Commands::from_u8(row.opcode);
+            if let Commands::None = template {
+                return None;
+            }
+            if row.operands.len() * 2 + 1 != template.len() {
+                return None;
+            }
+            let mut code = vec![row.opcode];
+            for (kind, value) in &row.operands {
+                code.extend([*kind, *value]);
+            }
+            script.add_command(Commands::from_u8_vec(code));
+        }
+        Some(script)
+    }
+
+    // Check the "NSL" magic bytes and the version of a header, returning the version
+    fn check_header(data: &[u8]) -> Result<u8, NslError> {
+        // Short reads are common, check the length before indexing into the header
+        if data.len() < 4 {
+            return Err(NslError::UnexpectedEof { at: 0, needed: 4 });
+        }
+        // Match first 3 characters to "NSL"
+        if data[..3] != [0x4E, 0x53, 0x4C] {
+            return Err(NslError::BadMagic);
+        }
+        let version = data[3];
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(NslError::UnsupportedVersion { version });
+        }
+        Ok(version)
+    }
+
+    // Decode a script from a reader one command at a time, without buffering the whole input.
+    // Stops after the first End command (leaving the rest of the reader untouched) or at the end of the input.
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: std::io::Read>(mut reader: R) -> Result<NSLScript, NslError> {
+        let read_exact = |reader: &mut R, buffer: &mut [u8], at: usize, needed: usize| {
+            reader.read_exact(buffer).map_err(|error| match error.kind() {
+                std::io::ErrorKind::UnexpectedEof => NslError::UnexpectedEof { at, needed },
+                _ => NslError::Io(error)
+            })
+        };
+        let mut header = [0u8; 4];
+        read_exact(&mut reader, &mut header, 0, 4)?;
+        let mut script = NSLScript::with_version(NSLScript::check_header(&header)?);
+
+        let mut i = 4;
+        loop {
+            let mut opcode = [0u8; 1];
+            match reader.read(&mut opcode) {
+                Ok(0) => break,
+                Ok(_) => {},
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(NslError::Io(error))
+            }
+            let len = opcode_len(opcode[0]);
+            if len == 0 {
+                return Err(NslError::UnknownOpcode { at: i, byte: opcode[0] });
+            }
+            let mut cmd_data = vec![0u8; len];
+            cmd_data[0] = opcode[0];
+            read_exact(&mut reader, &mut cmd_data[1..], i, len)?;
+            let cmd = Commands::from_u8_vec(cmd_data);
+            debug!("Read command {}: {:?}", i, cmd);
             i += len;
+            let end = matches!(cmd, Commands::End);
+            script.add_command(cmd);
+            if end {
+                break;
+            }
+        }
+        Ok(script)
+    }
+
+    // Returns true if the encoded script is at most max_bytes long
+    pub fn fits_in(&self, max_bytes: usize) -> bool {
+        self.len_bytes() <= max_bytes
+    }
+
+    // Encode the script, refusing to produce more than max_bytes (usually NGEN_MAX_SCRIPT_BYTES)
+    // or to encode a JumpLabel or JumpRel that hasn't been resolved
+    pub fn code_checked(&self, max_bytes: usize) -> Result<Vec<u8>, NslError> {
+        if let Some(index) = self.commands.iter().position(|command| matches!(command, Commands::JumpLabel(_) | Commands::JumpRel(_))) {
+            return Err(NslError::UnresolvedJump { index });
         }
-        Some(NSLScript {
-            commands: cmds
+        let size = self.len_bytes();
+        if size > max_bytes {
+            return Err(NslError::ScriptTooLarge { size, max: max_bytes });
+        }
+        Ok(self.code())
+    }
+
+    // Encode the script followed by an XOR checksum of the command bytes
+    pub fn code_with_checksum(&self) -> Vec<u8> {
+        let mut code = self.code();
+        code.push(checksum(&code[4..]));
+        code
+    }
+
+    // Check that the last byte of a script encoded with code_with_checksum matches its commands
+    pub fn verify_checksum(data: &[u8]) -> bool {
+        match data.split_last() {
+            Some((found, script)) if script.len() >= 4 => checksum(&script[4..]) == *found,
+            _ => false
+        }
+    }
+
+    // Decode a script encoded with code_with_checksum, rejecting it if the checksum doesn't match
+    pub fn try_from_u8_vec_checked(data: &[u8]) -> Result<NSLScript, NslError> {
+        if data.len() < 5 {
+            // Report a broken header first, otherwise the checksum byte is missing
+            NSLScript::try_from_u8_vec(data)?;
+            return Err(NslError::UnexpectedEof { at: data.len(), needed: 1 });
+        }
+        let (found, script) = (data[data.len() - 1], &data[..data.len() - 1]);
+        let expected = checksum(&script[4..]);
+        if expected != found {
+            return Err(NslError::ChecksumMismatch { expected, found });
+        }
+        NSLScript::try_from_u8_vec(script)
+    }
+
+    // Size of the encoded script in bytes (header included), computed without encoding it
+    pub fn len_bytes(&self) -> usize {
+        4 + self.commands.iter().map(|command| command.len()).sum::<usize>()
+    }
+
+    // Returns true if the script has no commands
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    // Iterate over the commands along with their byte offset in the encoded script
+    pub fn iter_with_offsets(&self) -> impl Iterator<Item = (usize, &Commands)> {
+        self.commands.iter().scan(4, |offset, command| {
+            let start = *offset;
+            *offset += command.len();
+            Some((start, command))
         })
     }
 
+    // Format the script as a listing with the byte offset, raw bytes and mnemonic of every command
+    pub fn listing(&self) -> String {
+        let mut listing = String::new();
+        for (offset, command) in self.iter_with_offsets() {
+            let bytes: Vec<String> = command.code().iter().map(|byte| format!("{:02X}", byte)).collect();
+            // The longest commands are 5 bytes long
+            listing.push_str(&format!("{:04X}: {:<14}   {}\n", offset, bytes.join(" "), command));
+        }
+        listing
+    }
+
+    // Export the script as a pretty printed JSON array with one object per command.
+    // Mnemonics and source names match the Display output, e.g.
+    // {"command": "SET", "operands": [{"source": "step_pitch", "value": 0}, {"source": "const", "value": 36}]}
+    // Memory buffer references are written as {"source": "mem", "buffer": 3}
+    pub fn to_json(&self) -> String {
+        let source_json = |source: &DataSource| {
+            let (key, value) = match source.inner() {
+                DataValue::Number(x) => ("value", x),
+                DataValue::Buffer(x) => ("buffer", x)
+            };
+            format!("{{\"source\": \"{}\", \"{}\": {}}}", source.kind().name(), key, value)
+        };
+        let mut lines: Vec<String> = Vec::new();
+        for command in &self.commands {
+            let mut line = format!("{{\"command\": \"{}\"", command.mnemonic());
+            match command {
+                Commands::Jump(x) => line.push_str(&format!(", \"address\": {}", x.get_value())),
+                Commands::JumpRel(x) => line.push_str(&format!(", \"relative\": {}", x)),
+                Commands::Label(name) | Commands::JumpLabel(name) => line.push_str(&format!(", \"label\": \"{}\"", json_escape(name))),
+                _ => {
+                    let operands: Vec<String> = if let Some((x, y)) = command.operands() {
+                        vec![source_json(x), source_json(y)]
+                    } else if let Some(x) = command.single_operand() {
+                        vec![source_json(x)]
+                    } else {
+                        Vec::new()
+                    };
+                    line.push_str(&format!(", \"operands\": [{}]", operands.join(", ")));
+                }
+            }
+            line.push('}');
+            lines.push(line);
+        }
+        if lines.is_empty() {
+            return String::from("[]");
+        }
+        format!("[\n  {}\n]", lines.join(",\n  "))
+    }
+
     pub fn get_info(&self) {
         for command in &self.commands {
-            info!(">> {:?}", command);
+            info!(">> {}", command);
         }
     }
 
-    // Import a hex file and convert it to a NSLScript
-    pub fn import_hex(path: &str) -> Option<NSLScript> {
-        let data = std::fs::read(path).unwrap();
-        NSLScript::from_u8_vec(data)
+    // Import a hex file and convert it to a NSLScript (None if the file isn't a valid script)
+    #[cfg(feature = "std")]
+    pub fn import_hex(path: &str) -> Result<Option<NSLScript>, std::io::Error> {
+        let data = std::fs::read(path)?;
+        Ok(NSLScript::from_u8_vec(data))
     }
 
     // Reads a hex file and returns a Vec<u8> value
-    pub fn import_hex_as_vec(path: &str) -> Vec<u8> {
-        std::fs::read(path).unwrap()
+    #[cfg(feature = "std")]
+    pub fn import_hex_as_vec(path: &str) -> Result<Vec<u8>, std::io::Error> {
+        std::fs::read(path)
     }
 
     // Export the script as a hex file
-    pub fn export_hex(&mut self, path: &str) {
+    #[cfg(feature = "std")]
+    pub fn export_hex(&self, path: &str) -> Result<(), std::io::Error> {
         let code = self.code();
-        std::fs::write(path, code).unwrap();
+        std::fs::write(path, code)
     }
 
 }
 
+/// Builds a NSLScript from `;` separated lowercase mnemonics (the same names the assembler uses).
+/// Operands are passed as arguments, usually with the DataSource helper functions.
+/// `jmp` takes an address, `jmp_rel` a relative offset, `label` and `jmp_label` take a label name.
+///
+/// ```
+/// use ngen_nsl::*;
+/// // examples/hello.rs
+/// let mut script = nsl![
+///     set(step_pitch(0), constant(36));
+///     set(step_velocity(0), constant(100));
+///     end
+/// ];
+/// assert_eq!(script.code(), vec![0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x02, 0x00, 0x00, 0x24, 0xA1, 0x03, 0x00, 0x00, 0x64, 0xFF]);
+///
+/// let script = nsl![
+///     label("start");
+///     loop(constant(4));
+///     add(memory_buffer(0), constant(1));
+///     loop_end;
+///     jmp_label("start");
+///     jmp(0x0004)
+/// ];
+/// assert_eq!(script.commands.len(), 6);
+/// assert_eq!(script.commands[5], Commands::Jump(Int16::new(0x00, 0x04)));
+/// ```
+#[macro_export]
+macro_rules! nsl {
+    ($($name:ident $(($($arg:expr),*))?);* $(;)?) => {{
+        let mut script = $crate::NSLScript::new();
+        $(script.add_command($crate::nsl!(@cmd $name $(($($arg),*))?));)*
+        script
+    }};
+    (@cmd set($x:expr, $y:expr)) => { $crate::Commands::Set($x, $y) };
+    (@cmd copy($x:expr, $y:expr)) => { $crate::Commands::Copy($x, $y) };
+    (@cmd clear_track) => { $crate::Commands::ClearTrack };
+    (@cmd clear_mem) => { $crate::Commands::ClearMemory };
+    (@cmd clear_all) => { $crate::Commands::ClearAll };
+    (@cmd select_track($x:expr)) => { $crate::Commands::SelectTrack($x) };
+    (@cmd clear_track_at($x:expr)) => { $crate::Commands::ClearTrackAt($x) };
+    (@cmd add($x:expr, $y:expr)) => { $crate::Commands::Add($x, $y) };
+    (@cmd sub($x:expr, $y:expr)) => { $crate::Commands::Subtract($x, $y) };
+    (@cmd mul($x:expr, $y:expr)) => { $crate::Commands::Multiply($x, $y) };
+    (@cmd div($x:expr, $y:expr)) => { $crate::Commands::Divide($x, $y) };
+    (@cmd quantize) => { $crate::Commands::QuantizePitch };
+    (@cmd gen_progression) => { $crate::Commands::GenerateProgression };
+    (@cmd euclidean($pulses:expr, $steps:expr)) => { $crate::Commands::GenerateEuclidean($pulses, $steps) };
+    (@cmd loop($x:expr)) => { $crate::Commands::LoopSet($x) };
+    (@cmd loop_end) => { $crate::Commands::LoopEnd };
+    (@cmd jmp($address:expr)) => { $crate::Commands::Jump($crate::Int16::from_slice(&u16::to_be_bytes($address))) };
+    (@cmd jmp_label($name:expr)) => { $crate::Commands::JumpLabel(::core::convert::Into::into($name)) };
+    (@cmd jmp_rel($offset:expr)) => { $crate::Commands::JumpRel($offset) };
+    (@cmd cond_e($x:expr, $y:expr)) => { $crate::Commands::CondE($x, $y) };
+    (@cmd cond_ne($x:expr, $y:expr)) => { $crate::Commands::CondNE($x, $y) };
+    (@cmd cond_gt($x:expr, $y:expr)) => { $crate::Commands::CondGT($x, $y) };
+    (@cmd cond_lt($x:expr, $y:expr)) => { $crate::Commands::CondLT($x, $y) };
+    (@cmd cond_gte($x:expr, $y:expr)) => { $crate::Commands::CondGTE($x, $y) };
+    (@cmd cond_lte($x:expr, $y:expr)) => { $crate::Commands::CondLTE($x, $y) };
+    (@cmd cond_end) => { $crate::Commands::CondEnd };
+    (@cmd end) => { $crate::Commands::End };
+    (@cmd label($name:expr)) => { $crate::Commands::Label(::core::convert::Into::into($name)) };
+}
+
+/// Script captured from NGEN, shared by the tests of every module and the test vectors
+#[cfg(any(test, feature = "test-vectors"))]
+pub(crate) const FIXTURE: &[u8] = &[0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x06, 0x00, 0x07, 0x00, 0xB3, 0x06, 0x00, 0x00, 0x03, 0xA1, 0x06, 0x01, 0x07, 0x01, 0xB3, 0x06, 0x01, 0x00, 0x03, 0xB6, 0x06, 0x00, 0x06, 0x01, 0xA1, 0x06, 0x00, 0x00, 0x00, 0xC0, 0x00, 0x20, 0xD2, 0x03, 0x80, 0x00, 0x00, 0xA1, 0x06, 0x01, 0x0A, 0x28, 0xA1, 0x02, 0x80, 0x08, 0x81, 0xB0, 0x02, 0x80, 0x00, 0x30, 0xA1, 0x04, 0x80, 0x00, 0x01, 0xA1, 0x05, 0x80, 0x01, 0x31, 0xD6, 0xB0, 0x06, 0x00, 0x00, 0x01, 0xC1];
+
 // Implement a simple test
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn thru_test() {
-        let test_script: Vec<u8> = vec![0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x06, 0x00, 0x07, 0x00, 0xB3, 0x06, 0x00, 0x00, 0x03, 0xA1, 0x06, 0x01, 0x07, 0x01, 0xB3, 0x06, 0x01, 0x00, 0x03, 0xB6, 0x06, 0x00, 0x06, 0x01, 0xA1, 0x06, 0x00, 0x00, 0x00, 0xC0, 0x00, 0x20, 0xD2, 0x03, 0x80, 0x00, 0x00, 0xA1, 0x06, 0x01, 0x0A, 0x28, 0xA1, 0x02, 0x80, 0x08, 0x81, 0xB0, 0x02, 0x80, 0x00, 0x30, 0xA1, 0x04, 0x80, 0x00, 0x01, 0xA1, 0x05, 0x80, 0x01, 0x31, 0xD6, 0xB0, 0x06, 0x00, 0x00, 0x01, 0xC1];
-        let mut script = NSLScript::from_u8_vec(test_script.clone()).unwrap();
+        let test_script: Vec<u8> = FIXTURE.to_vec();
+        let script = NSLScript::from_u8_vec(test_script.clone()).unwrap();
         let code = script.code();
 
         assert_eq!(test_script, code, "Verifying code... File: {} / Generated {}", test_script.len(), code.len());
 
     }
 
+    #[test]
+    fn select_track_roundtrip() {
+        let mut script = NSLScript::new();
+        script.add_command(Commands::SelectTrack(constant(2)));
+        script.add_command(Commands::Set(step_pitch(0), constant(36)));
+        script.add_command(Commands::End);
+        let code = script.code();
+        assert_eq!(code, vec![0x4E, 0x53, 0x4C, 0x01, 0xA6, 0x00, 0x02, 0xA1, 0x02, 0x00, 0x00, 0x24, 0xFF]);
+
+        let decoded = NSLScript::from_u8_vec(code.clone()).unwrap();
+        assert_eq!(decoded.commands[0], Commands::SelectTrack(constant(2)));
+        assert_eq!(decoded.code(), code);
+    }
+
+    #[test]
+    fn clear_track_forms() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::ClearTrack, Commands::ClearTrackAt(constant(3)), Commands::End]);
+        assert_eq!(Commands::ClearTrack.len(), 1);
+        assert_eq!(Commands::ClearTrackAt(constant(3)).len(), 3);
+        let code = script.code();
+        assert_eq!(code, vec![0x4E, 0x53, 0x4C, 0x01, 0xA3, 0xA7, 0x00, 0x03, 0xFF]);
+        assert_eq!(code.len(), script.len_bytes());
+
+        let decoded = NSLScript::from_u8_vec(code).unwrap();
+        assert_eq!(decoded.commands, script.commands);
+        assert!(NSLScript::from_u8_vec(vec![0x4E, 0x53, 0x4C, 0x01, 0xA7, 0x00]).is_none());
+    }
+
+    #[test]
+    fn jump_roundtrip() {
+        let mut script = NSLScript::new();
+        script.add_command(Commands::Jump(Int16::new(0x01, 0x20)));
+        let code = script.code();
+
+        let decoded = NSLScript::from_u8_vec(code).unwrap();
+        match &decoded.commands[0] {
+            Commands::Jump(target) => assert_eq!(target.get_value(), 0x0120),
+            cmd => panic!("Expected Jump, got {:?}", cmd)
+        }
+    }
+
+    #[test]
+    fn display_test() {
+        assert_eq!(Commands::Set(step_pitch(0), constant(36)).to_string(), "SET step_pitch[0] = const 36");
+        assert_eq!(Commands::Divide(memory_buffer(0), constant(3)).to_string(), "DIV mem[0] / const 3");
+        assert_eq!(Commands::CondGT(DataSource::Params(DataValue::Buffer(1)), DataSource::RandomNote(DataValue::Number(64))).to_string(), "COND_GT params[@1] > random_note 64");
+        assert_eq!(Commands::Jump(Int16::new(0x01, 0x20)).to_string(), "JMP 0x0120");
+        assert_eq!(Commands::End.to_string(), "END");
+    }
+
+    #[test]
+    fn saturating_arithmetic() {
+        let n = DataValue::Number;
+        assert_eq!(n(10).saturating_add(&n(20), 127), Some(n(30)));
+        assert_eq!(n(100).saturating_add(&n(100), 127), Some(n(127)));
+        assert_eq!(n(255).saturating_mul(&n(255), 127), Some(n(127)));
+        assert_eq!(n(20).saturating_add(&n(20), step_pitch(0).max()), Some(n(31)));
+        assert_eq!(n(5).saturating_sub(&n(10), 127), Some(n(0)));
+        assert_eq!(n(100).saturating_div(&n(3), 127), Some(n(33)));
+        assert_eq!(n(2).saturating_div(&n(3), 127), Some(n(0)));
+        assert_eq!(n(2).saturating_div(&n(0), 127), None);
+        assert_eq!(DataValue::Buffer(0).saturating_add(&n(1), 127), None);
+        assert_eq!(n(1).saturating_mul(&DataValue::Buffer(3), 127), None);
+    }
+
+    #[test]
+    fn buffer_index_clipping() {
+        let code = DataValue::Buffer(200).code();
+        assert_eq!(code, 0x80 + MEMORY_BUFFER_MAX);
+        assert!(matches!(DataValue::from_u8(code), DataValue::Buffer(MEMORY_BUFFER_MAX)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let mut script = NSLScript::new();
+        script.add_command(Commands::Set(step_pitch(0), constant(36)));
+        script.add_command(Commands::Jump(Int16::new(0x00, 0x04)));
+        script.add_command(Commands::End);
+
+        let json = serde_json::to_string(&script).unwrap();
+        assert!(json.contains(r#"{"Set":[{"StepPitch":{"Number":0}},{"Constant":{"Number":36}}]}"#));
+
+        let decoded: NSLScript = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.code(), script.code());
+    }
+
+    #[test]
+    fn offsets_test() {
+        let script = NSLScript::try_from_u8_vec(FIXTURE).unwrap();
+        let offsets: Vec<usize> = script.iter_with_offsets().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, vec![4, 9, 14, 19, 24, 29, 34, 37, 42, 47, 52, 57, 62, 67, 68, 73]);
+        for (offset, command) in script.iter_with_offsets() {
+            assert_eq!(FIXTURE[offset], command.cmd_code());
+        }
+    }
+
+    #[test]
+    fn checksum_test() {
+        let script = NSLScript::try_from_u8_vec(FIXTURE).unwrap();
+        let mut code = script.code_with_checksum();
+        assert_eq!(&code[..code.len() - 1], FIXTURE);
+        assert!(NSLScript::verify_checksum(&code));
+
+        let decoded = NSLScript::try_from_u8_vec_checked(&code).unwrap();
+        assert_eq!(decoded.code(), FIXTURE);
+
+        // Corrupt a value byte in the first command
+        code[6] ^= 0x01;
+        assert!(!NSLScript::verify_checksum(&code));
+        assert!(matches!(NSLScript::try_from_u8_vec_checked(&code), Err(NslError::ChecksumMismatch { .. })));
+        assert!(matches!(NSLScript::try_from_u8_vec_checked(&[0x4E, 0x53, 0x4C, 0x01]), Err(NslError::UnexpectedEof { at: 4, needed: 1 })));
+        assert!(!NSLScript::verify_checksum(&[0x00]));
+    }
+
+    #[test]
+    fn truncated_decoding() {
+        // Decoding every prefix of the fixture must never panic
+        for end in 0..FIXTURE.len() {
+            let data = &FIXTURE[..end];
+            let _ = NSLScript::from_u8_vec(data.to_vec());
+            let _ = Commands::from_u8_vec(data[end.min(4)..].to_vec());
+        }
+        assert!(matches!(Commands::from_u8_vec(vec![]), Commands::None));
+        assert!(matches!(Commands::from_u8_vec(vec![0xA1, 0x06]), Commands::None));
+        assert!(matches!(DataSource::from_u8_vec(vec![0x06]), DataSource::Constant(DataValue::Number(0))));
+        assert_eq!(Int16::from_u8_vec(vec![0x01]).get_value(), 0x0100);
+    }
+
+    #[test]
+    fn operands_test() {
+        let mut cmd = Commands::Add(memory_buffer(0), constant(3));
+        assert!(matches!(cmd.operands(), Some((DataSource::MemoryBuffer(_), DataSource::Constant(DataValue::Number(3))))));
+        if let Some((_, y)) = cmd.operands_mut() {
+            *y = constant(5);
+        }
+        assert_eq!(cmd.to_string(), "ADD mem[0] + const 5");
+        assert!(cmd.single_operand().is_none());
+
+        let cmd = Commands::LoopSet(constant(4));
+        assert!(cmd.operands().is_none());
+        assert!(matches!(cmd.single_operand(), Some(DataSource::Constant(DataValue::Number(4)))));
+        assert!(Commands::End.operands().is_none());
+    }
+
+    #[test]
+    fn classification() {
+        let cond = Commands::CondGTE(params(0), constant(64));
+        assert!(cond.is_conditional() && Commands::CondEnd.is_conditional());
+        assert!(!cond.is_loop_control() && !cond.is_arithmetic() && !cond.is_terminator());
+
+        assert!(Commands::LoopSet(constant(4)).is_loop_control() && Commands::LoopEnd.is_loop_control());
+        assert!(!Commands::Jump(Int16::new(0, 4)).is_loop_control());
+
+        let div = Commands::Divide(memory_buffer(0), constant(2));
+        assert!(div.is_arithmetic() && !div.is_conditional());
+        assert!(!Commands::Set(memory_buffer(0), constant(2)).is_arithmetic());
+
+        assert!(Commands::End.is_terminator() && !Commands::End.is_conditional());
+    }
+
+    #[test]
+    fn mnemonics_roundtrip() {
+        // Every encodable command, plus the pseudo-commands
+        let mut commands: Vec<Commands> = (0..=u8::MAX).map(Commands::from_u8).filter(|cmd| *cmd != Commands::None).collect();
+        assert_eq!(commands.len(), 25);
+        commands.extend([Commands::None, Commands::Label(String::from("start")), Commands::JumpLabel(String::from("start")), Commands::JumpRel(-4)]);
+        for command in commands {
+            let parsed = Commands::from_mnemonic(command.mnemonic()).unwrap();
+            assert_eq!(parsed.mnemonic(), command.mnemonic());
+            if !matches!(command, Commands::JumpLabel(_) | Commands::JumpRel(_)) {
+                assert_eq!(core::mem::discriminant(&parsed), core::mem::discriminant(&command));
+            }
+        }
+        assert_eq!(Commands::from_mnemonic("cond_gt"), Some(Commands::CondGT(constant(0), constant(0))));
+        assert_eq!(Commands::from_mnemonic("JMP"), Some(Commands::Jump(Int16::new(0, 0))));
+        assert_eq!(Commands::from_mnemonic("FOO"), None);
+    }
+
+    #[test]
+    fn split_concatenated_scripts() {
+        let mut first = NSLScript::new();
+        first.add_commands(vec![Commands::Set(step_pitch(0), constant(36)), Commands::End]);
+        let mut second = NSLScript::new();
+        second.add_commands(vec![Commands::LoopSet(constant(4)), Commands::Add(memory_buffer(0), constant(1)), Commands::LoopEnd, Commands::End]);
+
+        // One header, then the commands of both scripts
+        let mut data = first.code();
+        data.extend_from_slice(&second.code()[4..]);
+        let scripts = NSLScript::split_at_ends(&data).unwrap();
+        assert_eq!(scripts, vec![first.clone(), second]);
+
+        data.extend([0xA5]);
+        let scripts = NSLScript::split_at_ends(&data).unwrap();
+        assert_eq!(scripts.len(), 3);
+        assert_eq!(scripts[2].commands, vec![Commands::ClearAll]);
+
+        assert!(matches!(NSLScript::split_at_ends(&[0x4E, 0x53, 0x4C, 0x01, 0xFF, 0x42]), Err(NslError::UnknownOpcode { at: 5, byte: 0x42 })));
+        assert_eq!(NSLScript::split_at_ends(&[0x4E, 0x53, 0x4C, 0x01]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn opcode_is_leading_byte() {
+        let mut commands: Vec<Commands> = Commands::all_opcodes().iter().map(|(opcode, _, _)| Commands::from_u8(*opcode)).collect();
+        commands.extend([
+            Commands::SelectTrack(constant(3)),
+            Commands::Jump(Int16::new(0x01, 0x20)),
+            Commands::JumpLabel(String::from("start")),
+            Commands::JumpRel(-4),
+        ]);
+        for command in &commands {
+            assert_eq!(command.code().first(), Some(&command.opcode()), "{:?}", command);
+            assert_eq!(command.opcode(), command.cmd_code());
+        }
+        assert_eq!(Commands::SelectTrack(constant(0)).opcode(), 0xA6);
+        assert!(Commands::Label(String::from("start")).code().is_empty());
+        assert_eq!(Commands::None.opcode(), 0x00);
+    }
+
+    #[test]
+    fn clear_scopes() {
+        let clears = [
+            (0xA3, Commands::ClearTrack, ClearScope::Track),
+            (0xA4, Commands::ClearMemory, ClearScope::Memory),
+            (0xA5, Commands::ClearAll, ClearScope::All),
+        ];
+        for (opcode, command, scope) in clears {
+            let code = command.code();
+            assert_eq!(code, vec![opcode]);
+            let decoded = Commands::from_slice(&code);
+            assert_eq!(decoded, command);
+            assert!(decoded.is_clear());
+            assert_eq!(decoded.clear_scope(), Some(scope));
+        }
+        assert_eq!(Commands::ClearTrackAt(constant(2)).clear_scope(), Some(ClearScope::Track));
+        assert!(!Commands::End.is_clear() && Commands::Set(memory_buffer(0), constant(0)).clear_scope().is_none());
+
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::ClearAll, Commands::ClearMemory, Commands::ClearTrack, Commands::End]);
+        let decoded = NSLScript::try_from_u8_vec(&script.code()).unwrap();
+        let scopes: Vec<ClearScope> = decoded.commands.iter().filter_map(Commands::clear_scope).collect();
+        assert_eq!(scopes, [ClearScope::All, ClearScope::Memory, ClearScope::Track]);
+    }
+
+    #[test]
+    fn opcode_table() {
+        let table = Commands::all_opcodes();
+        for (opcode, mnemonic, len) in table {
+            let command = Commands::from_u8(*opcode);
+            assert_eq!(command.cmd_code(), *opcode);
+            assert_eq!(command.mnemonic(), *mnemonic);
+            assert_eq!(command.len(), *len);
+            assert_eq!(opcode_len(*opcode), *len);
+        }
+        // Everything else decodes to None
+        for opcode in 0..=u8::MAX {
+            let known = table.iter().any(|(code, _, _)| *code == opcode);
+            assert_eq!(Commands::from_u8(opcode) != Commands::None, known, "0x{:02X}", opcode);
+        }
+        assert!(table.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn replace_operands() {
+        let cmd = Commands::Set(memory_buffer(0), constant(36));
+        assert_eq!(cmd.with_operands(memory_buffer(5), constant(36)), Some(Commands::Set(memory_buffer(5), constant(36))));
+        assert_eq!(Commands::LoopSet(constant(4)).with_operands(constant(1), constant(2)), None);
+    }
+
+    #[test]
+    fn strict_trailing_bytes() {
+        let data = [0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x02, 0x00, 0x00, 0x24, 0xFF, 0x12, 0x34];
+        let (script, consumed) = NSLScript::from_u8_vec_strict(&data).unwrap();
+        assert_eq!(consumed, data.len() - 2);
+        assert_eq!(script.commands.len(), 2);
+        assert!(matches!(script.commands[1], Commands::End));
+
+        // Without the strict decoder the trailing bytes are decoded as commands
+        assert!(matches!(NSLScript::try_from_u8_vec(&data), Err(NslError::UnknownOpcode { at: 10, byte: 0x12 })));
+
+        let (script, consumed) = NSLScript::from_u8_vec_strict(FIXTURE).unwrap();
+        assert_eq!(consumed, FIXTURE.len());
+        assert_eq!(script.commands.len(), 16);
+    }
+
+    #[test]
+    fn value_accessors() {
+        let source = DataSource::from_u8_vec(vec![0x02, 0x83]);
+        assert!(source.inner().is_buffer());
+        assert!(!source.inner().is_number());
+        assert_eq!(source.inner().as_u8(), 3);
+
+        let source = constant(36);
+        assert!(source.inner().is_number());
+        assert_eq!(source.inner().as_u8(), 36);
+    }
+
+    #[test]
+    fn len_bytes_test() {
+        let script = NSLScript::try_from_u8_vec(FIXTURE).unwrap();
+        assert!(!script.is_empty());
+        assert_eq!(script.len_bytes(), script.code().len());
+
+        let script = NSLScript::new();
+        assert!(script.is_empty());
+        assert_eq!(script.len_bytes(), 4);
+    }
+
+    #[test]
+    fn script_size_limit() {
+        let mut script = NSLScript::new();
+        while script.len_bytes() < NGEN_MAX_SCRIPT_BYTES {
+            script.add_command(Commands::Set(memory_buffer(0), constant(1)));
+        }
+        assert_eq!(script.len_bytes(), NGEN_MAX_SCRIPT_BYTES);
+        assert!(script.fits_in(NGEN_MAX_SCRIPT_BYTES));
+        assert_eq!(script.code_checked(NGEN_MAX_SCRIPT_BYTES).unwrap().len(), NGEN_MAX_SCRIPT_BYTES);
+
+        script.add_command(Commands::End);
+        assert!(!script.fits_in(NGEN_MAX_SCRIPT_BYTES));
+        assert!(matches!(script.code_checked(NGEN_MAX_SCRIPT_BYTES), Err(NslError::ScriptTooLarge { size, max: NGEN_MAX_SCRIPT_BYTES }) if size == NGEN_MAX_SCRIPT_BYTES + 1));
+
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::Label(String::from("start")), Commands::JumpLabel(String::from("start"))]);
+        assert!(matches!(script.code_checked(NGEN_MAX_SCRIPT_BYTES), Err(NslError::UnresolvedJump { index: 1 })));
+        script.resolve_labels().unwrap();
+        assert!(script.code_checked(NGEN_MAX_SCRIPT_BYTES).is_ok());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn hex_file_io() {
+        let path = std::env::temp_dir().join(format!("ngen_nsl_test_{}.nsl", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let script = NSLScript::try_from_u8_vec(FIXTURE).unwrap();
+        script.export_hex(path).unwrap();
+        assert_eq!(NSLScript::import_hex_as_vec(path).unwrap(), FIXTURE);
+        let imported = NSLScript::import_hex(path).unwrap().unwrap();
+        assert_eq!(imported.code(), FIXTURE);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(NSLScript::import_hex(path).is_err());
+        assert!(NSLScript::import_hex_as_vec(path).is_err());
+    }
+
+    #[test]
+    fn structural_equality() {
+        let mut expected = NSLScript::new();
+        expected.add_command(Commands::Set(step_pitch(0), constant(36)));
+        expected.add_command(Commands::Jump(Int16::new(0x00, 0x04)));
+        expected.add_command(Commands::End);
+
+        let decoded = NSLScript::from_u8_vec(expected.code()).unwrap();
+        assert_eq!(decoded, expected);
+        assert_ne!(Commands::Set(step_pitch(0), constant(36)), Commands::Copy(step_pitch(0), constant(36)));
+        assert_ne!(DataValue::Number(3), DataValue::Buffer(3));
+    }
+
+    #[test]
+    fn listing_test() {
+        let script = NSLScript::try_from_u8_vec(FIXTURE).unwrap();
+        let listing = script.listing();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), script.commands.len());
+        assert_eq!(lines[0], "0004: A1 06 00 07 00   SET mem[0] = params[0]");
+        assert_eq!(lines[6], "0022: C0 00 20         LOOP const 32");
+        assert_eq!(lines[13], "0043: D6               COND_END");
+    }
+
+    #[test]
+    fn int16_14_bit() {
+        let value = Int16::from_u14(16383);
+        assert_eq!(value.code(), vec![0x7F, 0x7F]);
+        assert_eq!(value.get_value_14(), 16383);
+
+        let value = Int16::from_u14(300);
+        assert!(value.code().iter().all(|byte| *byte < 0x80));
+        assert_eq!(value.get_value_14(), 300);
+        assert_eq!(Int16::from_u8_vec(value.code()).get_value_14(), 300);
+        assert_eq!(Int16::from_u14(20000), Int16::from_u14(16383));
+    }
+
+    #[test]
+    fn sysex_safe_targets() {
+        // A jump target is a plain 16-bit offset, so a high bit in either byte can't go over SysEx as is
+        let target = Int16::new(0x81, 0x00);
+        assert_eq!(target.get_value(), 0x8100);
+        assert!(!target.is_sysex_safe());
+        assert!(!Int16::new(0x00, 0x80).is_sysex_safe());
+        assert!(Int16::new(0x01, 0x20).is_sysex_safe());
+        assert!(Int16::from_u14(0x3FFF).is_sysex_safe());
+    }
+
+    #[test]
+    fn data_source_kind() {
+        for (code, kind) in DataSourceKind::ALL.iter().enumerate() {
+            assert_eq!(kind.code() as usize, code);
+            assert_eq!(DataSourceKind::try_from(kind.code()), Ok(*kind));
+            assert_eq!(DataSource::new(*kind, DataValue::Number(1)).kind(), *kind);
+        }
+        assert_eq!(DataSourceKind::try_from(0x0B), Err(0x0B));
+        assert_eq!(step_velocity(3).kind(), DataSourceKind::StepVelocity);
+    }
+
+    #[test]
+    fn json_export() {
+        // examples/hello.rs
+        let mut script = NSLScript::new();
+        script.add_command(Commands::Set(step_pitch(0), constant(36)));
+        script.add_command(Commands::Set(step_velocity(0), constant(100)));
+        script.add_command(Commands::End);
+        assert_eq!(script.to_json(), concat!(
+            "[\n",
+            "  {\"command\": \"SET\", \"operands\": [{\"source\": \"step_pitch\", \"value\": 0}, {\"source\": \"const\", \"value\": 36}]},\n",
+            "  {\"command\": \"SET\", \"operands\": [{\"source\": \"step_velocity\", \"value\": 0}, {\"source\": \"const\", \"value\": 100}]},\n",
+            "  {\"command\": \"END\", \"operands\": []}\n",
+            "]"
+        ));
+
+        let mut script = NSLScript::new();
+        script.add_command(Commands::LoopSet(DataSource::MemoryBuffer(DataValue::Buffer(2))));
+        script.add_command(Commands::Jump(Int16::new(0x00, 0x04)));
+        script.add_command(Commands::Label(String::from("a\"b")));
+        assert_eq!(script.to_json(), concat!(
+            "[\n",
+            "  {\"command\": \"LOOP\", \"operands\": [{\"source\": \"mem\", \"buffer\": 2}]},\n",
+            "  {\"command\": \"JMP\", \"address\": 4},\n",
+            "  {\"command\": \"LABEL\", \"label\": \"a\\\"b\"}\n",
+            "]"
+        ));
+        assert_eq!(NSLScript::new().to_json(), "[]");
+    }
+
+    fn arb_data_source() -> impl Strategy<Value = DataSource> {
+        let value = prop_oneof![
+            (0u8..=127).prop_map(DataValue::Number),
+            (0u8..=MEMORY_BUFFER_MAX).prop_map(DataValue::Buffer),
+        ];
+        (0..DataSourceKind::ALL.len(), value).prop_map(|(kind, value)| {
+            let mut source = DataSource::new(DataSourceKind::ALL[kind], value);
+            source.validate();
+            source
+        })
+    }
+
+    fn arb_command() -> impl Strategy<Value = Commands> {
+        let pair = || (arb_data_source(), arb_data_source());
+        prop_oneof![
+            pair().prop_map(|(x, y)| Commands::Set(x, y)),
+            pair().prop_map(|(x, y)| Commands::Copy(x, y)),
+            pair().prop_map(|(x, y)| Commands::Add(x, y)),
+            pair().prop_map(|(x, y)| Commands::Subtract(x, y)),
+            pair().prop_map(|(x, y)| Commands::Multiply(x, y)),
+            pair().prop_map(|(x, y)| Commands::Divide(x, y)),
+            pair().prop_map(|(x, y)| Commands::GenerateEuclidean(x, y)),
+            pair().prop_map(|(x, y)| Commands::CondE(x, y)),
+            pair().prop_map(|(x, y)| Commands::CondNE(x, y)),
+            pair().prop_map(|(x, y)| Commands::CondGT(x, y)),
+            pair().prop_map(|(x, y)| Commands::CondLT(x, y)),
+            pair().prop_map(|(x, y)| Commands::CondGTE(x, y)),
+            pair().prop_map(|(x, y)| Commands::CondLTE(x, y)),
+            arb_data_source().prop_map(Commands::LoopSet),
+            arb_data_source().prop_map(Commands::SelectTrack),
+            arb_data_source().prop_map(Commands::ClearTrackAt),
+            any::<(u8, u8)>().prop_map(|(hi, lo)| Commands::Jump(Int16::new(hi, lo))),
+            Just(Commands::LoopEnd),
+            Just(Commands::ClearTrack),
+            Just(Commands::ClearMemory),
+            Just(Commands::ClearAll),
+            Just(Commands::QuantizePitch),
+            Just(Commands::GenerateProgression),
+            Just(Commands::CondEnd),
+            Just(Commands::End),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip_property(commands in proptest::collection::vec(arb_command(), 0..64)) {
+            let mut script = NSLScript::new();
+            script.add_commands(commands);
+            let code = script.code();
+            prop_assert_eq!(NSLScript::from_u8_vec(code), Some(script.clone()));
+            prop_assert!(script.roundtrip_ok());
+        }
+    }
+
+    #[test]
+    fn roundtrip_ok_test() {
+        let mut script = NSLScript::from_u8_vec(FIXTURE.to_vec()).unwrap();
+        assert!(script.roundtrip_ok());
+        script.add_label("start");
+        assert!(!script.roundtrip_ok());
+    }
+
+    #[test]
+    fn describe_test() {
+        assert_eq!(step_pitch(4).describe(), "Step Pitch [0–31]: 4");
+        assert_eq!(params(2).describe(), "Params [0–3]: 2");
+        assert_eq!(DataSource::Scale(DataValue::Buffer(3)).describe(), "Scale <- mem[3]");
+    }
+
+    #[test]
+    fn none_is_skipped() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(memory_buffer(0), constant(1)),
+            Commands::None,
+            Commands::Add(memory_buffer(0), constant(2)),
+        ]);
+        assert_eq!(Commands::None.code(), Vec::<u8>::new());
+        assert_eq!(script.len_bytes(), 14);
+        let decoded = NSLScript::from_u8_vec(script.code()).unwrap();
+        assert_eq!(decoded.commands, vec![
+            Commands::Set(memory_buffer(0), constant(1)),
+            Commands::Add(memory_buffer(0), constant(2)),
+        ]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn reader_decoding() {
+        let script = NSLScript::from_reader(FIXTURE).unwrap();
+        assert_eq!(Some(script), NSLScript::from_u8_vec(FIXTURE.to_vec()));
+
+        // Two scripts back to back in one stream
+        let mut archive = vec![0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x02, 0x00, 0x00, 0x24, 0xFF];
+        archive.extend([0x4E, 0x53, 0x4C, 0x01, 0xC1, 0xFF]);
+        let mut reader = std::io::Cursor::new(archive);
+        assert_eq!(NSLScript::from_reader(&mut reader).unwrap().commands.len(), 2);
+        assert_eq!(NSLScript::from_reader(&mut reader).unwrap().commands, vec![Commands::LoopEnd, Commands::End]);
+
+        assert!(matches!(NSLScript::from_reader(&[0x4E, 0x53][..]), Err(NslError::UnexpectedEof { at: 0, needed: 4 })));
+        assert!(matches!(NSLScript::from_reader(&[0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x06][..]), Err(NslError::UnexpectedEof { at: 4, needed: 5 })));
+        assert!(matches!(NSLScript::from_reader(&[0x4E, 0x53, 0x4C, 0x01, 0x42][..]), Err(NslError::UnknownOpcode { at: 4, byte: 0x42 })));
+    }
+
+    #[test]
+    fn euclidean_layout() {
+        let cmd = Commands::euclidean(constant(3), constant(8));
+        assert_eq!(cmd.code(), vec![0xB6, 0x00, 0x03, 0x00, 0x08]);
+    }
+
+    #[test]
+    fn signed_values() {
+        assert_eq!(DataValue::from_signed(-64).as_signed(), Some(-64));
+        assert_eq!(DataValue::from_signed(0), DataValue::Number(64));
+        assert_eq!(DataValue::from_signed(63), DataValue::Number(127));
+        assert_eq!(DataValue::from_signed(100), DataValue::Number(127));
+        assert_eq!(DataValue::Number(0).as_signed(), Some(-64));
+        assert_eq!(DataValue::Buffer(3).as_signed(), None);
+    }
+
+    #[test]
+    fn lengths_match_encoding() {
+        let mut commands: Vec<Commands> = (0..=255).map(Commands::from_u8).collect();
+        commands.push(Commands::Label(String::from("start")));
+        commands.push(Commands::JumpLabel(String::from("start")));
+        commands.push(Commands::JumpRel(-10));
+        for command in commands {
+            assert_eq!(command.len(), command.code().len(), "{:?}", command);
+            if !matches!(command, Commands::None | Commands::Label(_) | Commands::JumpLabel(_) | Commands::JumpRel(_)) {
+                assert_eq!(opcode_len(command.cmd_code()), command.len());
+            }
+        }
+        assert_eq!(opcode_len(0x42), 0);
+    }
+
+    #[test]
+    fn checked_constructors() {
+        assert_eq!(try_params(2), Ok(params(2)));
+        assert_eq!(try_params(5), Err(OutOfRange { kind: DataSourceKind::Params, value: 5, max: 3 }));
+        assert_eq!(try_constant(127), Ok(constant(127)));
+        // Values above 127 would otherwise be read as buffer references
+        assert!(try_constant(200).is_err());
+        assert!(try_random_note(101).is_err());
+    }
+
+    #[test]
+    fn large_script_length() {
+        let mut script = NSLScript::new();
+        for i in 0..1000 {
+            script.add_command(match i % 3 {
+                0 => Commands::Add(memory_buffer(0), constant(1)),
+                1 => Commands::LoopSet(constant(2)),
+                _ => Commands::LoopEnd
+            });
+        }
+        let size = script.len_bytes();
+        let code = script.code();
+        assert_eq!(code.len(), size);
+    }
+
+    #[test]
+    fn encoding_equality() {
+        // Both are clipped to step 31
+        let (a, mut b) = (step_pitch(31), step_pitch(40));
+        assert_ne!(a, b);
+        assert!(a.same_encoding(&b));
+        // Encoding alone doesn't clip
+        assert_ne!(a.code(), b.code());
+        b.validate();
+        assert_eq!(a.code(), b.code());
+        assert!(!a.same_encoding(&step_velocity(31)));
+        assert!(!constant(3).same_encoding(&DataSource::Constant(DataValue::Buffer(3))));
+    }
+
+    #[test]
+    fn out_of_range_encoding() {
+        // Above the source's maximum but within the byte format: written as is
+        assert_eq!(params(5).code(), vec![0x07, 0x05]);
+        assert_eq!(step_pitch(40).code(), vec![0x02, 40]);
+        // Outside the byte format: clamped so it can't turn into a buffer reference
+        assert_eq!(DataSource::Constant(DataValue::Number(200)).code(), vec![0x00, 0x7F]);
+        assert_eq!(DataSource::StepPitch(DataValue::Buffer(40)).code(), vec![0x02, 0x80 + MEMORY_BUFFER_MAX]);
+    }
+
+    #[test]
+    fn hex_strings() {
+        let script = NSLScript::try_from_u8_vec(FIXTURE).unwrap();
+        let hex = script.to_hex_str();
+        assert!(hex.starts_with("4E 53 4C 01 A1 06 00 07 00 B3"));
+        assert_eq!(NSLScript::from_hex_str(&hex).unwrap(), script);
+
+        let script = NSLScript::from_hex_str("0x4E, 0x53,0x4C 0x01\n\ta1 02 00 00 24, FF").unwrap();
+        assert_eq!(script.to_hex_str(), "4E 53 4C 01 A1 02 00 00 24 FF");
+        assert!(matches!(NSLScript::from_hex_str("4E 53 4C 01 FG"), Err(NslError::InvalidHex { token }) if token == "FG"));
+        assert!(matches!(NSLScript::from_hex_str("4E 53 4C 01 +F"), Err(NslError::InvalidHex { .. })));
+    }
+
+    #[test]
+    fn clip_warnings() {
+        assert!(DataSource::RandomNote(DataValue::Number(150)).would_clip());
+        assert!(!random_note(100).would_clip());
+        assert!(params(4).would_clip());
+        assert!(DataSource::Scale(DataValue::Buffer(40)).would_clip());
+        assert!(!DataSource::Scale(DataValue::Buffer(31)).would_clip());
+
+        let mut source = DataSource::Constant(DataValue::Number(200));
+        assert!(source.would_clip());
+        source.validate();
+        assert!(!source.would_clip());
+    }
+
+    #[test]
+    fn unknown_opcode_options() {
+        // A 3-byte command from newer firmware between two known commands
+        let data = [0x4E, 0x53, 0x4C, 0x01, 0xC1, 0xE0, 0x06, 0x01, 0xD6];
+        assert!(matches!(NSLScript::from_u8_vec_with(&data, DecodeOptions::default()), Err(NslError::UnknownOpcode { at: 5, byte: 0xE0 })));
+
+        let options = DecodeOptions { skip_unknown: true, assume_len: Some(3) };
+        let script = NSLScript::from_u8_vec_with(&data, options).unwrap();
+        assert_eq!(script.commands, vec![Commands::LoopEnd, Commands::CondEnd]);
+
+        // Skipping one byte at a time resyncs on 0x06 0x01 as unknown bytes too
+        let options = DecodeOptions { skip_unknown: true, assume_len: None };
+        let script = NSLScript::from_u8_vec_with(&data, options).unwrap();
+        assert_eq!(script.commands, vec![Commands::LoopEnd, Commands::CondEnd]);
+    }
+
+    #[test]
+    fn slice_conversions() {
+        let script = NSLScript::try_from(FIXTURE).unwrap();
+        assert_eq!(Some(&script), NSLScript::from_slice(FIXTURE).as_ref());
+        assert_eq!(Some(script), NSLScript::from_u8_vec(FIXTURE.to_vec()));
+        assert!(NSLScript::try_from(&FIXTURE[..6]).is_err());
+
+        assert_eq!(Commands::from_slice(&FIXTURE[4..9]), Commands::Set(memory_buffer(0), params(0)));
+        assert_eq!(DataSource::from_slice(&[0x07, 0x01]), params(1));
+        assert_eq!(Int16::from_slice(&[0x01, 0x20]).get_value(), 0x0120);
+    }
+
+    #[test]
+    fn default_values() {
+        assert_eq!(NSLScript::default(), NSLScript::new());
+        assert_eq!(NSLScript::default().version(), NSL_VERSION);
+        assert_eq!(DataValue::default(), DataValue::Number(0));
+        assert_eq!(DataSource::default(), constant(0));
+    }
+
+    #[test]
+    fn normalized_equality() {
+        let mut a = NSLScript::new();
+        a.add_commands(vec![
+            Commands::Set(DataSource::StepPitch(DataValue::Number(40)), DataSource::Constant(DataValue::Number(200))),
+            Commands::None,
+            Commands::LoopSet(DataSource::Params(DataValue::Buffer(30))),
+        ]);
+        let mut b = NSLScript::new();
+        b.add_commands(vec![
+            Commands::Set(step_pitch(31), constant(127)),
+            Commands::LoopSet(DataSource::Params(DataValue::Buffer(3))),
+        ]);
+        assert_ne!(a, b);
+        a.normalize();
+        b.normalize();
+        assert_eq!(a, b);
+        assert_eq!(a.code(), b.code());
+    }
+
+    #[test]
+    fn shared_encoding() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::Set(step_pitch(0), constant(36)), Commands::End]);
+        let shared = std::sync::Arc::new(script.clone());
+        let reference: &NSLScript = &shared;
+        assert_eq!(reference.code(), script.code());
+        assert_eq!(std::thread::spawn(move || shared.code()).join().unwrap(), script.code());
+    }
+
+    #[test]
+    fn step_view() {
+        assert_eq!(step_pitch(3).as_step(), Some((SeqKind::Pitch, &DataValue::Number(3))));
+        assert_eq!(step_velocity(0).as_step(), Some((SeqKind::Velocity, &DataValue::Number(0))));
+        assert_eq!(step_length(31).as_step(), Some((SeqKind::Length, &DataValue::Number(31))));
+        let source = DataSource::StepDensity(DataValue::Buffer(2));
+        assert_eq!(source.as_step(), Some((SeqKind::Density, &DataValue::Buffer(2))));
+        assert_eq!(memory_buffer(3).as_step(), None);
+        assert_eq!(constant(3).as_step(), None);
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::Set(step_pitch(0), constant(36)), Commands::End]);
+        assert!(script.insert_command(0, Commands::ClearAll));
+        assert!(script.insert_command(2, Commands::Set(step_velocity(0), constant(100))));
+        assert!(script.insert_command(4, Commands::End));
+        assert!(!script.insert_command(6, Commands::End));
+        assert_eq!(script.commands, vec![
+            Commands::ClearAll,
+            Commands::Set(step_pitch(0), constant(36)),
+            Commands::Set(step_velocity(0), constant(100)),
+            Commands::End,
+            Commands::End,
+        ]);
+
+        assert_eq!(script.remove_command(4), Some(Commands::End));
+        assert_eq!(script.remove_command(0), Some(Commands::ClearAll));
+        assert_eq!(script.remove_command(3), None);
+        assert_eq!(script.commands.len(), 3);
+    }
+
+    #[test]
+    fn writable_kinds() {
+        for kind in DataSourceKind::ALL {
+            let writable = DataSource::new(kind, DataValue::Number(0)).is_writable();
+            let expected = matches!(kind,
+                DataSourceKind::StepPitch
+                | DataSourceKind::StepVelocity
+                | DataSourceKind::StepLength
+                | DataSourceKind::StepDensity
+                | DataSourceKind::MemoryBuffer
+                | DataSourceKind::Params
+            );
+            assert_eq!(writable, expected, "{:?}", kind);
+        }
+        // Buffer references don't change the classification
+        assert!(DataSource::StepPitch(DataValue::Buffer(0)).is_writable());
+        assert!(!DataSource::Scale(DataValue::Buffer(0)).is_writable());
+    }
+
+    #[test]
+    fn fixed_size_bytes() {
+        for kind in DataSourceKind::ALL {
+            for value in [DataValue::Number(0), DataValue::Number(17), DataValue::Buffer(2)] {
+                let source = DataSource::new(kind, value);
+                let bytes = source.to_bytes();
+                assert_eq!(bytes.to_vec(), source.code());
+                assert_eq!(DataSource::from_bytes(bytes), source);
+            }
+        }
+        assert_eq!(step_pitch(3).to_bytes(), [0x02, 0x03]);
+        assert_eq!(DataSource::from_bytes([0x06, 0x81]), DataSource::MemoryBuffer(DataValue::Buffer(1)));
+    }
+
+    #[test]
+    fn hash_dedup() {
+        let mut a = NSLScript::new();
+        a.add_commands(vec![Commands::Set(step_pitch(0), constant(36)), Commands::End]);
+        let b = NSLScript::from_u8_vec(a.code()).unwrap();
+        let mut scripts = std::collections::HashSet::new();
+        scripts.insert(a.clone());
+        scripts.insert(b);
+        assert_eq!(scripts.len(), 1);
+
+        a.commands[0] = Commands::Set(step_pitch(0), constant(37));
+        scripts.insert(a);
+        assert_eq!(scripts.len(), 2);
+    }
+
+    #[test]
+    fn musical_transforms() {
+        // The script from examples/hello.rs
+        let mut script = NSLScript::new();
+        script.add_command(Commands::Set(step_pitch(0), constant(36)));
+        script.add_command(Commands::Set(step_velocity(0), constant(100)));
+        script.add_command(Commands::End);
+
+        script.transpose(7);
+        assert_eq!(script.commands[0], Commands::Set(step_pitch(0), constant(43)));
+        assert_eq!(script.commands[1], Commands::Set(step_velocity(0), constant(100)));
+        script.transpose(-50);
+        assert_eq!(script.commands[0], Commands::Set(step_pitch(0), constant(0)));
+
+        script.scale_velocity(0.5);
+        assert_eq!(script.commands[1], Commands::Set(step_velocity(0), constant(50)));
+        script.scale_velocity(4.0);
+        assert_eq!(script.commands[1], Commands::Set(step_velocity(0), constant(127)));
+        assert_eq!(script.commands[0], Commands::Set(step_pitch(0), constant(0)));
+
+        // Only constants paired with a step destination are touched
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Add(step_pitch(1), constant(12)),
+            Commands::Set(memory_buffer(0), constant(36)),
+            Commands::Set(step_pitch(2), memory_buffer(0)),
+        ]);
+        let untouched = script.commands[1..].to_vec();
+        script.transpose(2);
+        assert_eq!(script.commands[0], Commands::Add(step_pitch(1), constant(14)));
+        assert_eq!(script.commands[1..], untouched);
+
+        // Each note moves once, whether its constant is in the Set or in the Add
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(step_pitch(0), constant(36)),
+            Commands::Add(step_pitch(0), constant(12)),
+            Commands::Set(step_pitch(1), scale(3)),
+            Commands::Add(step_pitch(1), constant(48)),
+            Commands::Set(step_pitch(0), memory_buffer(0)),
+            Commands::Add(step_pitch(0), constant(12)),
+        ]);
+        script.transpose(7);
+        assert_eq!(script.commands, vec![
+            Commands::Set(step_pitch(0), constant(43)),
+            Commands::Add(step_pitch(0), constant(12)),
+            Commands::Set(step_pitch(1), scale(3)),
+            Commands::Add(step_pitch(1), constant(55)),
+            Commands::Set(step_pitch(0), memory_buffer(0)),
+            Commands::Add(step_pitch(0), constant(19)),
+        ]);
+    }
+
+    #[test]
+    fn transpose_with_map_sources() {
+        let melody = [36, 43, 48, 41];
+        let mut script = NSLScript::new();
+        for (i, pitch) in melody.iter().enumerate() {
+            script.add_command(Commands::Set(step_pitch(i as u8), constant(*pitch)));
+        }
+        script.add_command(Commands::End);
+
+        let mut visited = 0;
+        script.map_sources(|source| {
+            visited += 1;
+            if let DataSource::Constant(DataValue::Number(x)) = source {
+                *x += 12;
+            }
+        });
+        assert_eq!(visited, 8);
+        for (i, pitch) in melody.iter().enumerate() {
+            assert_eq!(script.commands[i], Commands::Set(step_pitch(i as u8), constant(pitch + 12)));
+        }
+
+        let mut looped = NSLScript::new();
+        looped.add_commands(vec![Commands::LoopSet(constant(4)), Commands::Jump(Int16::new(0x00, 0x04))]);
+        looped.map_sources(|source| *source = memory_buffer(1));
+        assert_eq!(looped.commands, vec![Commands::LoopSet(memory_buffer(1)), Commands::Jump(Int16::new(0x00, 0x04))]);
+    }
+
+    #[test]
+    fn encode_into_reuses_buffer() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::Set(step_pitch(0), constant(36)), Commands::End]);
+        let mut buffer = Vec::new();
+        script.encode_into(&mut buffer);
+        assert_eq!(buffer, script.code());
+        let capacity = buffer.capacity();
+
+        // The previous contents are replaced, not appended to, and the allocation is kept
+        script.commands[0] = Commands::ClearAll;
+        script.encode_into(&mut buffer);
+        assert_eq!(buffer, vec![0x4E, 0x53, 0x4C, 0x01, 0xA5, 0xFF]);
+        assert_eq!(buffer.capacity(), capacity);
+    }
+
+    #[test]
+    fn count_without_decoding() {
+        assert_eq!(NSLScript::count_commands(FIXTURE).unwrap(), NSLScript::from_u8_vec(FIXTURE.to_vec()).unwrap().commands.len());
+        assert_eq!(NSLScript::count_commands(&[0x4E, 0x53, 0x4C, 0x01]).unwrap(), 0);
+        assert!(matches!(NSLScript::count_commands(&[0x4E, 0x53, 0x4C, 0x01, 0xC1, 0x42]), Err(NslError::UnknownOpcode { at: 5, byte: 0x42 })));
+        assert!(matches!(NSLScript::count_commands(&[0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x06]), Err(NslError::UnexpectedEof { at: 4, needed: 5 })));
+    }
+
+    #[test]
+    fn recover_from_junk() {
+        let mut data = FIXTURE.to_vec();
+        // Corrupt two bytes between the Divide at 0x09 and the Set at 0x0E
+        data.splice(0x0E..0x0E, [0x42, 0x13]);
+        assert!(NSLScript::try_from_u8_vec(&data).is_err());
+
+        let (script, junk) = NSLScript::decode_recovering(&data).unwrap();
+        assert_eq!(script, NSLScript::try_from_u8_vec(FIXTURE).unwrap());
+        assert_eq!(junk, vec![JunkRegion { at: 0x0E, bytes: vec![0x42, 0x13] }]);
+
+        // A command cut off at the end is junk too
+        let (script, junk) = NSLScript::decode_recovering(&[0x4E, 0x53, 0x4C, 0x01, 0xA5, 0xA1, 0x06]).unwrap();
+        assert_eq!(script.commands, vec![Commands::ClearAll]);
+        assert_eq!(junk, vec![JunkRegion { at: 5, bytes: vec![0xA1, 0x06] }]);
+
+        assert!(matches!(NSLScript::decode_recovering(&[0x00; 4]), Err(NslError::BadMagic)));
+    }
+
+    #[test]
+    fn error_messages() {
+        let message = |error: NslError| error.to_string();
+        assert_eq!(message(NslError::BadMagic), "data doesn't start with the NSL header");
+        assert_eq!(message(NslError::UnexpectedEof { at: 4, needed: 5 }), "data ended before the 5 bytes starting at offset 4");
+        assert_eq!(message(NslError::UnknownOpcode { at: 5, byte: 0x42 }), "unknown opcode 0x42 at offset 5");
+        assert_eq!(message(NslError::ScriptTooLarge { size: 300, max: 256 }), "script is 300 bytes, more than the 256 bytes allowed");
+        assert_eq!(message(NslError::ChecksumMismatch { expected: 0x12, found: 0x34 }), "checksum mismatch: expected 0x12, found 0x34");
+        assert_eq!(message(NslError::UnsupportedVersion { version: 2 }), "unsupported NSL version 2");
+        assert_eq!(message(NslError::InvalidHex { token: String::from("zz") }), "invalid hex byte \"zz\"");
+        assert_eq!(message(try_step_pitch(40).unwrap_err().into()), "40 is above the maximum of 31 for Step Pitch");
+        assert_eq!(message(NslError::UnbalancedControl { index: 3 }), "unbalanced loop or conditional at command 3");
+        assert_eq!(message(NslError::InvalidSysEx { at: 7 }), "invalid SysEx byte at offset 7");
+        assert_eq!(message(NslError::UnresolvedJump { index: 2 }), "unresolved jump at command 2");
+        assert_eq!(message(NslError::TooManyScripts { count: 300, max: 255 }), "bank holds 300 scripts, more than the 255 allowed");
+        assert_eq!(message(NslError::TrailingData { at: 29, len: 2 }), "2 unexpected bytes at offset 29");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn io_error_messages() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "missing.nsl");
+        assert_eq!(NslError::from(io).to_string(), "I/O error: missing.nsl");
+        let error: Box<dyn std::error::Error> = Box::new(NslError::from(std::io::Error::other("disk")));
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert!(matches!(NSLScript::try_from_u8_vec(&[0x4E, 0x53, 0x4D, 0x01]), Err(NslError::BadMagic)));
+        assert!(matches!(NSLScript::try_from_u8_vec(&[0x4E, 0x53]), Err(NslError::UnexpectedEof { at: 0, needed: 4 })));
+        assert!(matches!(NSLScript::try_from_u8_vec(&[]), Err(NslError::UnexpectedEof { at: 0, needed: 4 })));
+        assert!(NSLScript::from_u8_vec(vec![0x00]).is_none());
+        assert!(NSLScript::from_u8_vec(vec![0x4E, 0x53, 0x4C]).is_none());
+        // Set command cut off after its first operand
+        assert!(matches!(NSLScript::try_from_u8_vec(&[0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x06, 0x00]), Err(NslError::UnexpectedEof { at: 4, needed: 5 })));
+        assert!(matches!(NSLScript::try_from_u8_vec(&[0x4E, 0x53, 0x4C, 0x01, 0xC1, 0x42]), Err(NslError::UnknownOpcode { at: 5, byte: 0x42 })));
+        assert!(NSLScript::from_u8_vec(vec![0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x06]).is_none());
+        assert!(matches!(NSLScript::try_from_u8_vec(&[0x4E, 0x53, 0x4C, 0x02, 0xFF]), Err(NslError::UnsupportedVersion { version: 2 })));
+    }
+
+    #[test]
+    fn append_relocates_jumps() {
+        let mut first = NSLScript::new();
+        first.add_commands(vec![
+            Commands::Add(memory_buffer(0), constant(1)),
+            Commands::Jump(Int16::new(0x00, 0x04)),
+            Commands::End,
+        ]);
+        let mut second = NSLScript::new();
+        second.add_commands(vec![
+            Commands::Set(memory_buffer(1), constant(2)),
+            Commands::Add(memory_buffer(1), constant(1)),
+            Commands::Jump(Int16::new(0x00, 0x09)),
+            Commands::End,
+        ]);
+        first.append(&second).unwrap();
+        assert_eq!(first.commands.len(), 6);
+        assert_eq!(first.commands[1], Commands::Jump(Int16::new(0x00, 0x04)));
+        // The first fragment without End is 8 bytes long
+        assert_eq!(first.commands[4], Commands::Jump(Int16::new(0x00, 0x11)));
+        assert_eq!(first.validate(), Ok(()));
+
+        let offsets: Vec<(usize, &Commands)> = first.iter_with_offsets().collect();
+        assert_eq!(offsets[0], (0x04, &Commands::Add(memory_buffer(0), constant(1))));
+        assert_eq!(offsets[3], (0x11, &Commands::Add(memory_buffer(1), constant(1))));
+
+        // Relocating past the 16 bit address space is an error and leaves the script as it was
+        let mut far = NSLScript::new();
+        far.add_commands(vec![Commands::Set(memory_buffer(0), constant(0)), Commands::Jump(Int16::new(0xFF, 0xFE))]);
+        let before = first.clone();
+        assert_eq!(first.append(&far), Err(LabelError::JumpOutOfRange { index: 1, target: 0xFFFE + 0x15 }));
+        assert_eq!(first, before);
+    }
+
+    #[test]
+    fn rows_roundtrip() {
+        let script = NSLScript::from_u8_vec(FIXTURE.to_vec()).unwrap();
+        let rows = script.to_rows();
+        assert_eq!(rows.len(), script.commands.len());
+        assert_eq!(rows[0], CommandRow { opcode: 0xA1, operands: vec![(0x06, 0x00), (0x07, 0x00)] });
+        assert_eq!(rows[6], CommandRow { opcode: 0xC0, operands: vec![(0x00, 0x20)] });
+        assert_eq!(rows[15], CommandRow { opcode: 0xC1, operands: vec![] });
+        assert_eq!(NSLScript::from_rows(&rows), Some(script));
+
+        assert!(NSLScript::from_rows(&[CommandRow { opcode: 0x42, operands: vec![] }]).is_none());
+        assert!(NSLScript::from_rows(&[CommandRow { opcode: 0xA1, operands: vec![(0x06, 0x00)] }]).is_none());
+    }
+
+    #[test]
+    fn header_version() {
+        let script = NSLScript::from_u8_vec(FIXTURE.to_vec()).unwrap();
+        assert_eq!(script.version(), NSL_VERSION);
+
+        let mut script = NSLScript::with_version(0x02);
+        script.add_command(Commands::End);
+        assert_eq!(script.version(), 0x02);
+        assert_eq!(script.code(), vec![0x4E, 0x53, 0x4C, 0x02, 0xFF]);
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,216 @@
+/*
+
+Metadata stored alongside a script
+
+The binary format has no room for a name or description, so they are kept in a companion
+JSON file next to the `.nsl` file (`bassline.nsl` + `bassline.json`):
+
+    {"name": "Bassline", "description": "Random walk over the scale"}
+
+*/
+
+use alloc::format;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use crate::NSLScript;
+use crate::json_escape;
+
+/// Name and description of a script, kept out of the encoded binary
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScriptMeta {
+    pub name: String,
+    pub description: String,
+}
+
+impl ScriptMeta {
+    /// Creates metadata with the given name and description
+    pub fn new(name: &str, description: &str) -> ScriptMeta {
+        ScriptMeta {
+            name: String::from(name),
+            description: String::from(description),
+        }
+    }
+
+    /// Encodes the metadata as a JSON object
+    pub fn to_json(&self) -> String {
+        format!("{{\"name\": \"{}\", \"description\": \"{}\"}}", json_escape(&self.name), json_escape(&self.description))
+    }
+
+    /// Decodes metadata written by `to_json` or by any JSON encoder, returning None if the text
+    /// isn't a JSON object of strings.
+    /// Unknown keys are ignored and missing keys are left empty.
+    pub fn from_json(text: &str) -> Option<ScriptMeta> {
+        let mut meta = ScriptMeta::default();
+        let mut chars = text.trim().chars().peekable();
+        if chars.next()? != '{' {
+            return None;
+        }
+        skip_whitespace(&mut chars);
+        if chars.peek() == Some(&'}') {
+            chars.next();
+            return chars.next().is_none().then_some(meta);
+        }
+        loop {
+            skip_whitespace(&mut chars);
+            let key = parse_string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            if chars.next()? != ':' {
+                return None;
+            }
+            skip_whitespace(&mut chars);
+            let value = parse_string(&mut chars)?;
+            match key.as_str() {
+                "name" => meta.name = value,
+                "description" => meta.description = value,
+                _ => {}
+            }
+            skip_whitespace(&mut chars);
+            match chars.next()? {
+                ',' => continue,
+                '}' => break,
+                _ => return None
+            }
+        }
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Some(meta),
+            Some(_) => None
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut core::iter::Peekable<core::str::Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+/// Parses a JSON string literal, undoing every escape of the JSON grammar (including UTF-16
+/// surrogate pairs such as `\uD83D\uDE00`). Returns None on an unknown escape or lone surrogate.
+fn parse_string(chars: &mut core::iter::Peekable<core::str::Chars>) -> Option<String> {
+    if chars.next()? != '"' {
+        return None;
+    }
+    let mut text = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(text),
+            '\\' => match chars.next()? {
+                c @ ('"' | '\\' | '/') => text.push(c),
+                'b' => text.push('\u{08}'),
+                'f' => text.push('\u{0C}'),
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                'r' => text.push('\r'),
+                'u' => {
+                    let code = match parse_hex4(chars)? {
+                        high @ 0xD800..=0xDBFF => {
+                            if chars.next()? != '\\' || chars.next()? != 'u' {
+                                return None;
+                            }
+                            let low = parse_hex4(chars)?;
+                            if !(0xDC00..=0xDFFF).contains(&low) {
+                                return None;
+                            }
+                            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                        },
+                        code => code
+                    };
+                    text.push(char::from_u32(code)?);
+                },
+                _ => return None
+            },
+            c => text.push(c)
+        }
+    }
+}
+
+/// Parses the 4 hex digits of a `\u` escape
+fn parse_hex4(chars: &mut core::iter::Peekable<core::str::Chars>) -> Option<u32> {
+    let mut code = 0;
+    for _ in 0..4 {
+        code = code * 16 + chars.next()?.to_digit(16)?;
+    }
+    Some(code)
+}
+
+#[cfg(feature = "std")]
+impl NSLScript {
+    /// Exports the script as a binary file at `path` and, if the script has metadata, a companion
+    /// `.json` file with the same name. The binary file is exactly what `export_hex` writes.
+    pub fn export_with_meta(&self, path: &str) -> Result<(), std::io::Error> {
+        self.export_hex(path)?;
+        if let Some(meta) = &self.metadata {
+            std::fs::write(std::path::Path::new(path).with_extension("json"), meta.to_json())?;
+        }
+        Ok(())
+    }
+
+    /// Imports a binary file written by `export_with_meta` along with its companion `.json` file, if any.
+    /// Returns None if the binary file isn't a valid script, and ignores a sidecar that can't be parsed.
+    pub fn import_with_meta(path: &str) -> Result<Option<NSLScript>, std::io::Error> {
+        let mut script = match NSLScript::import_hex(path)? {
+            Some(script) => script,
+            None => return Ok(None)
+        };
+        match std::fs::read_to_string(std::path::Path::new(path).with_extension("json")) {
+            Ok(text) => script.metadata = ScriptMeta::from_json(&text),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {},
+            Err(error) => return Err(error)
+        }
+        Ok(Some(script))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::*;
+
+    #[test]
+    fn json_roundtrip() {
+        let meta = ScriptMeta::new("Bass \"line\"", "Random walk\nover the scale");
+        assert_eq!(meta.to_json(), "{\"name\": \"Bass \\\"line\\\"\", \"description\": \"Random walk\\nover the scale\"}");
+        assert_eq!(ScriptMeta::from_json(&meta.to_json()), Some(meta));
+
+        assert_eq!(ScriptMeta::from_json(" { \"description\" : \"x\", \"extra\": \"\" } "), Some(ScriptMeta::new("", "x")));
+        assert_eq!(ScriptMeta::from_json("{}"), Some(ScriptMeta::default()));
+        assert_eq!(ScriptMeta::from_json("{\"name\": 1}"), None);
+        assert_eq!(ScriptMeta::from_json("{\"name\": \"x\"} trailing"), None);
+    }
+
+    #[test]
+    fn json_escapes() {
+        let text = r#"{"name": "a\/b\b\f\\\u00e9", "description": "\uD83D\uDE00"}"#;
+        assert_eq!(ScriptMeta::from_json(text), Some(ScriptMeta::new("a/b\u{08}\u{0C}\\\u{e9}", "\u{1F600}")));
+        // Lone surrogates, unknown escapes and malformed \u escapes are rejected
+        assert_eq!(ScriptMeta::from_json(r#"{"name": "\uD83D"}"#), None);
+        assert_eq!(ScriptMeta::from_json(r#"{"name": "\uDE00"}"#), None);
+        assert_eq!(ScriptMeta::from_json(r#"{"name": "\q"}"#), None);
+        assert_eq!(ScriptMeta::from_json(r#"{"name": "\u+0e9"}"#), None);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn sidecar_roundtrip() {
+        let path = std::env::temp_dir().join(format!("ngen_nsl_meta_{}.nsl", std::process::id()));
+        let sidecar = path.with_extension("json");
+        let path = path.to_str().unwrap();
+
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::Set(step_pitch(0), constant(36)), Commands::End]);
+        script.metadata = Some(ScriptMeta::new("Hello", "Sets the first step"));
+        script.export_with_meta(path).unwrap();
+        // The binary stays device ready
+        assert_eq!(NSLScript::import_hex_as_vec(path).unwrap(), script.code());
+        assert_eq!(NSLScript::import_with_meta(path).unwrap(), Some(script.clone()));
+
+        std::fs::remove_file(&sidecar).unwrap();
+        let imported = NSLScript::import_with_meta(path).unwrap().unwrap();
+        assert_eq!(imported.metadata, None);
+        assert_eq!(imported.commands, script.commands);
+        std::fs::remove_file(path).unwrap();
+    }
+}
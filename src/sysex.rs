@@ -0,0 +1,131 @@
+/*
+
+MIDI SysEx transport
+
+Scripts travel to NGEN inside a SysEx message. SysEx data bytes must be below 0x80, which the
+opcodes aren't, so the encoded script is packed 7 bytes at a time behind a byte holding their
+high bits:
+
+    F0              SysEx start
+    ID ...          manufacturer ID (1 byte, or 3 bytes starting with 00)
+    MM D0 .. D6     bit n of MM is the high bit of Dn, followed by the low 7 bits of the 7 bytes
+    ...             (the last block can be shorter)
+    F7              SysEx end
+
+*/
+
+use alloc::vec::Vec;
+use crate::{NSLScript, NslError};
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+
+/// Packs 8-bit data into bytes below 0x80 with the MIDI 7-in-8 scheme: every block of up to 7 bytes
+/// is preceded by a byte whose bit n is the high bit of the n-th byte of the block
+pub fn encode_7bit(data: &[u8]) -> Vec<u8> {
+    let mut packed = Vec::with_capacity(data.len() + data.len().div_ceil(7));
+    for block in data.chunks(7) {
+        let msbs = block.iter().enumerate().fold(0, |msbs, (n, byte)| msbs | ((byte >> 7) << n));
+        packed.push(msbs);
+        packed.extend(block.iter().map(|byte| byte & 0x7F));
+    }
+    packed
+}
+
+/// Unpacks data packed by `encode_7bit`. High bits set in the packed bytes are ignored.
+pub fn decode_7bit(data: &[u8]) -> Vec<u8> {
+    let mut unpacked = Vec::with_capacity(data.len() - data.len().div_ceil(8));
+    for block in data.chunks(8) {
+        let (msbs, bytes) = block.split_first().unwrap_or((&0, &[]));
+        unpacked.extend(bytes.iter().enumerate().map(|(n, byte)| (byte & 0x7F) | (((msbs >> n) & 1) << 7)));
+    }
+    unpacked
+}
+
+impl NSLScript {
+    /// Encodes the script and wraps it in a SysEx message from `manufacturer_id`,
+    /// which must be a valid (7-bit) 1 or 3 byte MIDI manufacturer ID
+    pub fn to_sysex(&self, manufacturer_id: &[u8]) -> Vec<u8> {
+        let packed = encode_7bit(&self.code());
+        let mut message = Vec::with_capacity(packed.len() + manufacturer_id.len() + 2);
+        message.push(SYSEX_START);
+        message.extend_from_slice(manufacturer_id);
+        message.extend(packed);
+        message.push(SYSEX_END);
+        message
+    }
+
+    /// Decodes a script from a SysEx message made by `to_sysex`, whatever its manufacturer ID
+    pub fn from_sysex(data: &[u8]) -> Result<NSLScript, NslError> {
+        let (first, last) = match (data.first(), data.last()) {
+            (Some(first), Some(last)) if data.len() >= 3 => (*first, *last),
+            _ => return Err(NslError::UnexpectedEof { at: 0, needed: 3 })
+        };
+        if first != SYSEX_START {
+            return Err(NslError::InvalidSysEx { at: 0 });
+        }
+        if last != SYSEX_END {
+            return Err(NslError::InvalidSysEx { at: data.len() - 1 });
+        }
+        // Extended manufacturer IDs are 00 followed by two more bytes
+        let start = if data[1] == 0x00 { 4 } else { 2 };
+        let body = data.get(start..data.len() - 1).ok_or(NslError::UnexpectedEof { at: 1, needed: start - 1 })?;
+        if let Some(at) = data[1..data.len() - 1].iter().position(|byte| *byte >= 0x80) {
+            return Err(NslError::InvalidSysEx { at: at + 1 });
+        }
+        NSLScript::try_from_u8_vec(&decode_7bit(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn sysex_roundtrip() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::LoopSet(constant(4)),
+            Commands::Set(step_pitch(0), random_note(40)),
+            Commands::LoopEnd,
+            Commands::End,
+        ]);
+        let message = script.to_sysex(&[0x7D]);
+        assert_eq!(message[..2], [0xF0, 0x7D]);
+        assert_eq!(message.last(), Some(&0xF7));
+        assert!(message[1..message.len() - 1].iter().all(|byte| *byte < 0x80));
+        assert_eq!(NSLScript::from_sysex(&message).unwrap(), script);
+
+        let message = script.to_sysex(&[0x00, 0x21, 0x7F]);
+        assert_eq!(message[..4], [0xF0, 0x00, 0x21, 0x7F]);
+        assert_eq!(NSLScript::from_sysex(&message).unwrap(), script);
+
+        // 4 header bytes and 10 command bytes are packed into 2 full blocks
+        assert_eq!(encode_7bit(&script.code()).len(), 16);
+    }
+
+    #[test]
+    fn pack_7bit() {
+        let packed = encode_7bit(FIXTURE);
+        assert_eq!(packed.len(), FIXTURE.len() + FIXTURE.len().div_ceil(7));
+        assert!(packed.iter().all(|byte| *byte < 0x80));
+        assert_eq!(decode_7bit(&packed), FIXTURE);
+
+        assert_eq!(encode_7bit(&[0xFF, 0x01, 0x80]), [0b101, 0x7F, 0x01, 0x00]);
+        assert_eq!(decode_7bit(&[0b101, 0x7F, 0x01, 0x00]), [0xFF, 0x01, 0x80]);
+        assert!(encode_7bit(&[]).is_empty() && decode_7bit(&[]).is_empty());
+    }
+
+    #[test]
+    fn bad_sysex_frames() {
+        let message = NSLScript::new().to_sysex(&[0x7D]);
+        assert!(matches!(NSLScript::from_sysex(&message[1..]), Err(NslError::InvalidSysEx { at: 0 })));
+        assert!(matches!(NSLScript::from_sysex(&message[..message.len() - 1]), Err(NslError::InvalidSysEx { .. })));
+        assert!(matches!(NSLScript::from_sysex(&[0xF0, 0xF7]), Err(NslError::UnexpectedEof { .. })));
+
+        let mut corrupt = message.clone();
+        corrupt[3] = 0xA1;
+        assert!(matches!(NSLScript::from_sysex(&corrupt), Err(NslError::InvalidSysEx { at: 3 })));
+    }
+}
@@ -0,0 +1,126 @@
+/*
+
+Known-good encoded scripts for conformance tests (behind the `test-vectors` feature)
+
+Each vector pairs the encoded bytes with the script they decode to, so crates building on
+ngen_nsl can check their own encoders and decoders against them. Only `EUCLIDEAN_MELODY` was
+captured from a device; `COUNTED_LOOP`, `ACCENTS` and `EUCLIDEAN_TRACK` are synthesized by
+hand to cover buffer indexes, nested conditionals and track commands, and follow the format
+without having been loaded on NGEN:
+
+    for (data, script) in ngen_nsl::test_vectors::all() {
+        assert_eq!(script.code(), data);
+    }
+
+*/
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::{constant, memory_buffer, params, random, random_note, step_velocity, Commands, DataSource, DataValue, NSLScript};
+
+/// Random melody on the euclidean rhythm set by params 0 and 1. Loops, conditionals and
+/// buffer references, with no End: the script the crate's own tests were written against.
+pub const EUCLIDEAN_MELODY: &[u8] = crate::FIXTURE;
+
+/// Returns the script encoded by `EUCLIDEAN_MELODY`
+pub fn euclidean_melody() -> (&'static [u8], NSLScript) {
+    let mut script = NSLScript::new();
+    script.add_commands(vec![
+        Commands::Set(memory_buffer(0), params(0)),
+        Commands::Divide(memory_buffer(0), constant(3)),
+        Commands::Set(memory_buffer(1), params(1)),
+        Commands::Divide(memory_buffer(1), constant(3)),
+        Commands::GenerateEuclidean(memory_buffer(0), memory_buffer(1)),
+        Commands::Set(memory_buffer(0), constant(0)),
+        Commands::LoopSet(constant(32)),
+        Commands::CondGT(DataSource::StepVelocity(DataValue::Buffer(0)), constant(0)),
+        Commands::Set(memory_buffer(1), random_note(40)),
+        Commands::Set(DataSource::StepPitch(DataValue::Buffer(0)), DataSource::Scale(DataValue::Buffer(1))),
+        Commands::Add(DataSource::StepPitch(DataValue::Buffer(0)), constant(48)),
+        Commands::Set(DataSource::StepLength(DataValue::Buffer(0)), constant(1)),
+        Commands::Set(DataSource::StepDensity(DataValue::Buffer(0)), random(49)),
+        Commands::CondEnd,
+        Commands::Add(memory_buffer(0), constant(1)),
+        Commands::LoopEnd,
+    ]);
+    (EUCLIDEAN_MELODY, script)
+}
+
+/// Rising arpeggio: a loop writing the steps 0 to 15 through a memory buffer counter
+pub const COUNTED_LOOP: &[u8] = &[0x4E, 0x53, 0x4C, 0x01, 0xA1, 0x06, 0x00, 0x00, 0x00, 0xA1, 0x06, 0x01, 0x00, 0x24, 0xC0, 0x00, 0x10, 0xA1, 0x02, 0x80, 0x06, 0x81, 0xA1, 0x03, 0x80, 0x00, 0x64, 0xB0, 0x06, 0x01, 0x00, 0x02, 0xB0, 0x06, 0x00, 0x00, 0x01, 0xC1, 0xFF];
+
+/// Returns the script encoded by `COUNTED_LOOP`
+pub fn counted_loop() -> (&'static [u8], NSLScript) {
+    let mut script = NSLScript::new();
+    script.add_commands(vec![
+        Commands::Set(memory_buffer(0), constant(0)),
+        Commands::Set(memory_buffer(1), constant(36)),
+        Commands::LoopSet(constant(16)),
+        Commands::Set(DataSource::StepPitch(DataValue::Buffer(0)), DataSource::MemoryBuffer(DataValue::Buffer(1))),
+        Commands::Set(DataSource::StepVelocity(DataValue::Buffer(0)), constant(100)),
+        Commands::Add(memory_buffer(1), constant(2)),
+        Commands::Add(memory_buffer(0), constant(1)),
+        Commands::LoopEnd,
+        Commands::End,
+    ]);
+    (COUNTED_LOOP, script)
+}
+
+/// Accents chosen by param 0: nested and sibling conditionals on the velocity of step 0
+pub const ACCENTS: &[u8] = &[0x4E, 0x53, 0x4C, 0x01, 0xD4, 0x07, 0x00, 0x00, 0x40, 0xA1, 0x03, 0x00, 0x00, 0x7F, 0xD0, 0x07, 0x01, 0x00, 0x00, 0xA1, 0x03, 0x08, 0x00, 0x6E, 0xD6, 0xD6, 0xD3, 0x07, 0x00, 0x00, 0x40, 0xA1, 0x03, 0x00, 0x01, 0x50, 0xD6, 0xFF];
+
+/// Returns the script encoded by `ACCENTS`
+pub fn accents() -> (&'static [u8], NSLScript) {
+    let mut script = NSLScript::new();
+    script.add_commands(vec![
+        Commands::CondGTE(params(0), constant(64)),
+        Commands::Set(step_velocity(0), constant(127)),
+        Commands::CondE(params(1), constant(0)),
+        Commands::Set(step_velocity(8), constant(110)),
+        Commands::CondEnd,
+        Commands::CondEnd,
+        Commands::CondLT(params(0), constant(64)),
+        Commands::Set(step_velocity(0), random(80)),
+        Commands::CondEnd,
+        Commands::End,
+    ]);
+    (ACCENTS, script)
+}
+
+/// Five hits over sixteen steps on the second track, left to the device's euclidean generator
+pub const EUCLIDEAN_TRACK: &[u8] = &[0x4E, 0x53, 0x4C, 0x01, 0xA6, 0x00, 0x01, 0xA3, 0xB6, 0x00, 0x05, 0x00, 0x10, 0xFF];
+
+/// Returns the script encoded by `EUCLIDEAN_TRACK`
+pub fn euclidean_track() -> (&'static [u8], NSLScript) {
+    let mut script = NSLScript::new();
+    script.add_commands(vec![
+        Commands::SelectTrack(constant(1)),
+        Commands::ClearTrack,
+        Commands::GenerateEuclidean(constant(5), constant(16)),
+        Commands::End,
+    ]);
+    (EUCLIDEAN_TRACK, script)
+}
+
+/// Returns every test vector
+pub fn all() -> Vec<(&'static [u8], NSLScript)> {
+    vec![euclidean_melody(), counted_loop(), accents(), euclidean_track()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ValidationWarning;
+
+    #[test]
+    fn vectors_roundtrip() {
+        for (data, script) in all() {
+            assert_eq!(script.code(), data);
+            assert_eq!(NSLScript::try_from_u8_vec(data).unwrap(), script);
+            assert_eq!(NSLScript::count_commands(data).unwrap(), script.commands.len());
+            assert_eq!(script.validate(), Ok(()));
+        }
+        // The device fixture has no End, which is only a warning
+        assert_eq!(euclidean_melody().1.validate_warnings(), vec![ValidationWarning::MissingEnd { index: 16 }]);
+    }
+}
@@ -0,0 +1,449 @@
+/*
+
+Static validation of NSL scripts before they are sent to the device
+
+*/
+
+use alloc::vec::Vec;
+use crate::{Commands, DataSource, DataValue, NSLScript};
+
+/// Maximum number of loops that can be nested inside each other on NGEN
+pub const NGEN_MAX_LOOP_DEPTH: usize = 4;
+/// Default maximum number of loops and conditionals, combined, that can be open at once.
+/// The size of the device's control stack isn't documented, use `validate_with_nesting` to check against another limit.
+pub const NGEN_MAX_NESTING: usize = 8;
+
+/// Problems found by `NSLScript::validate`. Each error carries the index of the offending command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The LoopSet at `index` is never closed by a LoopEnd
+    UnclosedLoop { index: usize },
+    /// The LoopEnd at `index` doesn't close an open LoopSet
+    UnmatchedLoopEnd { index: usize },
+    /// The conditional at `index` is never closed by a CondEnd
+    UnclosedCond { index: usize },
+    /// The CondEnd at `index` doesn't close an open conditional
+    UnmatchedCondEnd { index: usize },
+    /// The LoopSet at `index` nests loops `depth` levels deep, more than `NGEN_MAX_LOOP_DEPTH`
+    LoopTooDeep { index: usize, depth: usize },
+    /// The LoopSet or conditional at `index` opens a block `depth` levels deep, more than the nesting limit
+    NestingTooDeep { index: usize, depth: usize },
+    /// The Jump at `jump_index` targets a byte inside another command
+    JumpIntoCommandBody { jump_index: usize, target: u16 },
+    /// The Jump at `jump_index` targets the header or a byte past the last command
+    JumpOutOfRange { jump_index: usize, target: u16 },
+    /// The JumpLabel or JumpRel at `jump_index` hasn't been resolved into a Jump
+    UnresolvedJump { jump_index: usize },
+    /// The Divide at `index` divides by the constant 0
+    DivideByConstantZero { index: usize },
+    /// The Set, Copy or arithmetic command at `index` writes to the read-only `source`
+    NonWritableDestination { index: usize, source: DataSource },
+}
+
+impl ValidationError {
+    /// Returns the index of the command the error refers to
+    pub fn index(&self) -> usize {
+        match self {
+            ValidationError::UnclosedLoop { index }
+            | ValidationError::UnmatchedLoopEnd { index }
+            | ValidationError::UnclosedCond { index }
+            | ValidationError::UnmatchedCondEnd { index }
+            | ValidationError::LoopTooDeep { index, .. }
+            | ValidationError::NestingTooDeep { index, .. }
+            | ValidationError::NonWritableDestination { index, .. }
+            | ValidationError::DivideByConstantZero { index } => *index,
+            ValidationError::JumpIntoCommandBody { jump_index, .. }
+            | ValidationError::JumpOutOfRange { jump_index, .. }
+            | ValidationError::UnresolvedJump { jump_index } => *jump_index,
+        }
+    }
+}
+
+/// Suspicious but loadable constructs found by `NSLScript::validate_warnings`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationWarning {
+    /// The script has no End command, `index` is the number of commands (where End should go)
+    MissingEnd { index: usize },
+    /// The command at `index` comes after the first End and never runs
+    CommandAfterEnd { index: usize },
+    /// The End at `index` isn't the first End of the script
+    MultipleEnds { index: usize },
+}
+
+impl ValidationWarning {
+    /// Returns the index of the command the warning refers to
+    pub fn index(&self) -> usize {
+        match self {
+            ValidationWarning::MissingEnd { index }
+            | ValidationWarning::CommandAfterEnd { index }
+            | ValidationWarning::MultipleEnds { index } => *index,
+        }
+    }
+}
+
+/// Problems found by `NSLScript::validate_jumps`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JumpError {
+    /// The Jump at `jump_index` targets the header or a byte past the last command,
+    /// including addresses that wrapped around from a negative offset
+    OutOfRange { jump_index: usize, target: u16 },
+    /// The Jump at `jump_index` targets a byte inside another command
+    Misaligned { jump_index: usize, target: u16 },
+    /// The JumpLabel or JumpRel at `jump_index` hasn't been resolved (see `resolve_labels` and `resolve_relative`)
+    Unresolved { jump_index: usize },
+}
+
+impl From<JumpError> for ValidationError {
+    fn from(error: JumpError) -> ValidationError {
+        match error {
+            JumpError::OutOfRange { jump_index, target } => ValidationError::JumpOutOfRange { jump_index, target },
+            JumpError::Misaligned { jump_index, target } => ValidationError::JumpIntoCommandBody { jump_index, target },
+            JumpError::Unresolved { jump_index } => ValidationError::UnresolvedJump { jump_index },
+        }
+    }
+}
+
+/// Blocks that are opened and closed by a pair of commands
+#[derive(Debug, PartialEq)]
+enum Block {
+    Loop,
+    Cond,
+}
+
+impl NSLScript {
+    /// Checks that every loop and conditional is balanced, that loops and conditionals don't nest too deep,
+    /// that every jump lands on the start of a command and that nothing divides by a constant 0.
+    /// See `validate_warnings` for the End terminator.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        self.validate_with_nesting(NGEN_MAX_NESTING)
+    }
+
+    /// Same as `validate`, flagging blocks nested more than `max_nesting` levels deep
+    pub fn validate_with_nesting(&self, max_nesting: usize) -> Result<(), Vec<ValidationError>> {
+        let mut errors: Vec<ValidationError> = match self.validate_jumps() {
+            Ok(()) => Vec::new(),
+            Err(jump_errors) => jump_errors.into_iter().map(ValidationError::from).collect()
+        };
+        let mut open: Vec<(Block, usize)> = Vec::new();
+        let mut loop_depth = 0;
+
+        for (index, command) in self.commands.iter().enumerate() {
+            match command {
+                Commands::LoopSet(_) => {
+                    loop_depth += 1;
+                    if loop_depth > NGEN_MAX_LOOP_DEPTH {
+                        errors.push(ValidationError::LoopTooDeep { index, depth: loop_depth });
+                    }
+                    open.push((Block::Loop, index));
+                    if open.len() > max_nesting {
+                        errors.push(ValidationError::NestingTooDeep { index, depth: open.len() });
+                    }
+                },
+                Commands::LoopEnd => {
+                    if let Some((Block::Loop, _)) = open.last() {
+                        open.pop();
+                        loop_depth -= 1;
+                    } else {
+                        errors.push(ValidationError::UnmatchedLoopEnd { index });
+                    }
+                },
+                Commands::CondE(_, _)
+                | Commands::CondNE(_, _)
+                | Commands::CondGT(_, _)
+                | Commands::CondLT(_, _)
+                | Commands::CondGTE(_, _)
+                | Commands::CondLTE(_, _) => {
+                    open.push((Block::Cond, index));
+                    if open.len() > max_nesting {
+                        errors.push(ValidationError::NestingTooDeep { index, depth: open.len() });
+                    }
+                },
+                Commands::CondEnd => {
+                    if let Some((Block::Cond, _)) = open.last() {
+                        open.pop();
+                    } else {
+                        errors.push(ValidationError::UnmatchedCondEnd { index });
+                    }
+                },
+                // Buffer values are only known at run time
+                Commands::Divide(_, DataSource::Constant(DataValue::Number(0))) => {
+                    errors.push(ValidationError::DivideByConstantZero { index });
+                },
+                _ => {}
+            }
+        }
+
+        for (block, index) in open {
+            errors.push(match block {
+                Block::Loop => ValidationError::UnclosedLoop { index },
+                Block::Cond => ValidationError::UnclosedCond { index },
+            });
+        }
+        errors.sort_by_key(ValidationError::index);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Reports what usually makes the device fail to load a script without making it invalid:
+    /// a missing End, commands after the first End and extra Ends. A well-formed script has
+    /// exactly one End, as its last command.
+    pub fn validate_warnings(&self) -> Vec<ValidationWarning> {
+        let mut warnings = Vec::new();
+        match self.end_position() {
+            None => warnings.push(ValidationWarning::MissingEnd { index: self.commands.len() }),
+            Some(end) => {
+                // Only the first stray command is reported, the rest of the tail is just as dead
+                let mut after_end = false;
+                for (index, command) in self.commands.iter().enumerate().skip(end + 1) {
+                    match command {
+                        Commands::End => warnings.push(ValidationWarning::MultipleEnds { index }),
+                        _ if !after_end => {
+                            warnings.push(ValidationWarning::CommandAfterEnd { index });
+                            after_end = true;
+                        },
+                        _ => {}
+                    }
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Checks that the commands make sense for their operands: Set, Copy and the arithmetic commands
+    /// must write to a step, the memory buffer or a param, not to a constant, random or scale source
+    pub fn validate_semantics(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        for (index, command) in self.commands.iter().enumerate() {
+            let writes = matches!(command, Commands::Set(_, _) | Commands::Copy(_, _)) || command.is_arithmetic();
+            match command.operands() {
+                Some((x, _)) if writes && !x.is_writable() => {
+                    errors.push(ValidationError::NonWritableDestination { index, source: x.clone() });
+                },
+                _ => {}
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks only the jumps: every target must be the start of a command of this script, and every
+    /// JumpLabel and JumpRel must have been resolved. Cheaper than `validate` and enough to catch
+    /// stale addresses before uploading.
+    pub fn validate_jumps(&self) -> Result<(), Vec<JumpError>> {
+        let starts: Vec<usize> = self.iter_with_offsets().map(|(offset, _)| offset).collect();
+        let end = self.len_bytes();
+        let mut errors = Vec::new();
+        for (jump_index, command) in self.commands.iter().enumerate() {
+            match command {
+                Commands::Jump(x) => {
+                    let target = x.get_value();
+                    if (target as usize) < 4 || target as usize >= end {
+                        errors.push(JumpError::OutOfRange { jump_index, target });
+                    } else if !starts.contains(&(target as usize)) {
+                        errors.push(JumpError::Misaligned { jump_index, target });
+                    }
+                },
+                Commands::JumpLabel(_) | Commands::JumpRel(_) => errors.push(JumpError::Unresolved { jump_index }),
+                _ => {}
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn balanced_script() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::LoopSet(constant(4)),
+            Commands::CondGT(step_pitch(0), constant(64)),
+            Commands::Set(step_pitch(0), constant(64)),
+            Commands::CondEnd,
+            Commands::LoopEnd,
+            Commands::End,
+        ]);
+        assert_eq!(script.validate(), Ok(()));
+    }
+
+    #[test]
+    fn missing_loop_end() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(memory_buffer(0), constant(1)),
+            Commands::LoopSet(constant(4)),
+            Commands::Add(memory_buffer(0), constant(1)),
+            Commands::End,
+        ]);
+        assert_eq!(script.validate(), Err(vec![ValidationError::UnclosedLoop { index: 1 }]));
+    }
+
+    #[test]
+    fn stray_cond_end() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::CondE(memory_buffer(0), constant(1)),
+            Commands::CondEnd,
+            Commands::CondEnd,
+            Commands::End,
+        ]);
+        assert_eq!(script.validate(), Err(vec![ValidationError::UnmatchedCondEnd { index: 2 }]));
+    }
+
+    #[test]
+    fn jump_targets() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(memory_buffer(0), constant(0)),
+            Commands::Add(memory_buffer(0), constant(1)),
+            // Start of the Add command
+            Commands::Jump(Int16::new(0x00, 0x09)),
+            Commands::End,
+        ]);
+        assert_eq!(script.validate(), Ok(()));
+
+        // One byte past the start of the Add command
+        script.commands[2] = Commands::Jump(Int16::new(0x00, 0x0A));
+        assert_eq!(script.validate(), Err(vec![ValidationError::JumpIntoCommandBody { jump_index: 2, target: 0x0A }]));
+
+        script.commands[2] = Commands::Jump(Int16::new(0x00, 0x02));
+        assert_eq!(script.validate(), Err(vec![ValidationError::JumpOutOfRange { jump_index: 2, target: 0x02 }]));
+
+        script.commands[2] = Commands::Jump(Int16::new(0x00, script.len_bytes() as u8));
+        assert_eq!(script.validate(), Err(vec![ValidationError::JumpOutOfRange { jump_index: 2, target: 18 }]));
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Divide(memory_buffer(0), constant(0)),
+            Commands::Divide(memory_buffer(0), DataSource::Constant(DataValue::Buffer(0))),
+            Commands::Divide(memory_buffer(0), memory_buffer(1)),
+        ]);
+        assert_eq!(script.validate(), Err(vec![ValidationError::DivideByConstantZero { index: 0 }]));
+    }
+
+    #[test]
+    fn loop_too_deep() {
+        let mut script = NSLScript::new();
+        for _ in 0..NGEN_MAX_LOOP_DEPTH + 1 {
+            script.add_command(Commands::LoopSet(constant(2)));
+        }
+        for _ in 0..NGEN_MAX_LOOP_DEPTH + 1 {
+            script.add_command(Commands::LoopEnd);
+        }
+        assert_eq!(script.validate(), Err(vec![ValidationError::LoopTooDeep { index: NGEN_MAX_LOOP_DEPTH, depth: NGEN_MAX_LOOP_DEPTH + 1 }]));
+    }
+
+    #[test]
+    fn end_terminator() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::Set(step_pitch(0), constant(36)), Commands::End]);
+        assert_eq!(script.end_position(), Some(1));
+        assert_eq!(script.validate_warnings(), vec![]);
+
+        // Warnings never make a script invalid
+        script.commands.pop();
+        assert_eq!(script.end_position(), None);
+        assert_eq!(script.validate_warnings(), vec![ValidationWarning::MissingEnd { index: 1 }]);
+        assert_eq!(script.validate(), Ok(()));
+
+        script.commands.insert(0, Commands::End);
+        assert_eq!(script.end_position(), Some(0));
+        assert_eq!(script.validate_warnings(), vec![ValidationWarning::CommandAfterEnd { index: 1 }]);
+
+        script.commands.push(Commands::End);
+        script.commands.push(Commands::End);
+        assert_eq!(script.validate_warnings(), vec![
+            ValidationWarning::CommandAfterEnd { index: 1 },
+            ValidationWarning::MultipleEnds { index: 2 },
+            ValidationWarning::MultipleEnds { index: 3 },
+        ]);
+        assert_eq!(script.validate(), Ok(()));
+    }
+
+    #[test]
+    fn jump_bounds() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Add(memory_buffer(0), constant(1)),
+            Commands::Jump(Int16::new(0x00, 0x04)),
+            Commands::End,
+        ]);
+        assert_eq!(script.validate_jumps(), Ok(()));
+
+        script.commands[1] = Commands::Jump(Int16::new(0x01, 0x00));
+        assert_eq!(script.validate_jumps(), Err(vec![JumpError::OutOfRange { jump_index: 1, target: 0x0100 }]));
+
+        // A jump one byte back from address 0 wraps around to the top of the address space
+        let [hi, lo] = 0u16.wrapping_sub(1).to_be_bytes();
+        script.commands[1] = Commands::Jump(Int16::new(hi, lo));
+        assert_eq!(script.validate_jumps(), Err(vec![JumpError::OutOfRange { jump_index: 1, target: 0xFFFF }]));
+
+        script.commands[1] = Commands::Jump(Int16::new(0x00, 0x05));
+        assert_eq!(script.validate_jumps(), Err(vec![JumpError::Misaligned { jump_index: 1, target: 0x05 }]));
+        assert_eq!(script.validate(), Err(vec![ValidationError::JumpIntoCommandBody { jump_index: 1, target: 0x05 }]));
+
+        script.commands[1] = Commands::JumpRel(-5);
+        assert_eq!(script.validate_jumps(), Err(vec![JumpError::Unresolved { jump_index: 1 }]));
+        script.commands[1] = Commands::JumpLabel(String::from("start"));
+        assert_eq!(script.validate(), Err(vec![ValidationError::UnresolvedJump { jump_index: 1 }]));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let mut script = NSLScript::new();
+        for _ in 0..NGEN_MAX_NESTING {
+            script.add_command(Commands::CondGT(params(0), constant(64)));
+        }
+        for _ in 0..NGEN_MAX_NESTING {
+            script.add_command(Commands::CondEnd);
+        }
+        script.add_command(Commands::End);
+        assert_eq!(script.validate(), Ok(()));
+
+        script.commands.insert(NGEN_MAX_NESTING, Commands::LoopSet(constant(2)));
+        script.commands.insert(NGEN_MAX_NESTING + 1, Commands::LoopEnd);
+        let depth = NGEN_MAX_NESTING + 1;
+        assert_eq!(script.validate(), Err(vec![ValidationError::NestingTooDeep { index: NGEN_MAX_NESTING, depth }]));
+
+        // A lower limit flags the outer blocks too
+        assert_eq!(script.validate_with_nesting(NGEN_MAX_NESTING + 1), Ok(()));
+        assert_eq!(script.validate_with_nesting(NGEN_MAX_NESTING - 1).unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn writable_destinations() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(memory_buffer(0), constant(6)),
+            Commands::Add(step_pitch(0), random(12)),
+            Commands::CondGT(constant(5), constant(6)),
+            Commands::CondEnd,
+            Commands::End,
+        ]);
+        assert_eq!(script.validate_semantics(), Ok(()));
+
+        script.commands[0] = Commands::Set(constant(5), constant(6));
+        script.commands[1] = Commands::Add(scale(0), random(12));
+        assert_eq!(script.validate_semantics(), Err(vec![
+            ValidationError::NonWritableDestination { index: 0, source: constant(5) },
+            ValidationError::NonWritableDestination { index: 1, source: scale(0) },
+        ]));
+    }
+}
@@ -0,0 +1,662 @@
+/*
+
+Offline interpreter for NSL scripts
+
+The Machine models the state a script can read and write on NGEN: the memory buffer,
+the four params and the step sequences of every track. Step sources and ClearTrack refer to
+the active track, which is changed by SelectTrack, while ClearTrackAt names its track
+explicitly. QuantizePitch snaps the active track's pitches to the machine's scale. Commands
+that need the device's other generators (progressions, euclidean patterns) are skipped as no-ops.
+
+Like `Set`, every two-operand command treats x as the destination and y as the source.
+Results of Add, Subtract and Multiply outside 0-127 are handled according to the machine's
+`OverflowMode` (saturating by default).
+
+*/
+
+use alloc::vec;
+use alloc::vec::Vec;
+use log::debug;
+use crate::{Commands, DataSource, DataValue, NSLScript};
+use crate::gen::Scale;
+
+/// Number of slots in the memory buffer
+pub const MEMORY_SIZE: usize = 32;
+/// Number of steps in each sequence of a track
+pub const SEQUENCE_LENGTH: usize = 32;
+/// Number of params
+pub const PARAMS_COUNT: usize = 4;
+/// Number of tracks of a machine created with `Machine::new` or `Machine::default`
+pub const TRACK_COUNT: usize = 4;
+/// Highest value a step, memory slot or param can hold
+const VALUE_MAX: u8 = 127;
+
+/// Errors that stop a script from running. `index` is the index of the command being executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    /// The script ran for `max_steps` commands without reaching the end
+    StepLimit { max_steps: usize },
+    /// The destination of the command at `index` is a read-only source
+    NotWritable { index: usize },
+    /// The command at `index` divides by zero
+    DivideByZero { index: usize },
+    /// The Jump at `index` doesn't land on the start of a command
+    BadJump { index: usize, target: u16 },
+    /// The LoopEnd at `index` doesn't close an open loop
+    UnmatchedLoopEnd { index: usize },
+    /// The arithmetic command at `index` produced a result outside 0-127 with `OverflowMode::Error`
+    Overflow { index: usize },
+}
+
+/// How Add, Subtract and Multiply handle results outside 0-127
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    /// Clamp the result to 0-127, the same way DataSource values are clipped
+    #[default]
+    Saturate,
+    /// Wrap the result around modulo 128
+    Wrap,
+    /// Stop the script with `VmError::Overflow`
+    Error,
+}
+
+impl OverflowMode {
+    /// Brings an arithmetic result into 0-127, returning None with `OverflowMode::Error`
+    fn apply(&self, result: i32) -> Option<u8> {
+        let max = VALUE_MAX as i32;
+        match self {
+            OverflowMode::Saturate => Some(result.clamp(0, max) as u8),
+            OverflowMode::Wrap => Some(result.rem_euclid(max + 1) as u8),
+            OverflowMode::Error if (0..=max).contains(&result) => Some(result as u8),
+            OverflowMode::Error => None
+        }
+    }
+}
+
+/// Step sequences of a track
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Track {
+    pub pitch: [u8; SEQUENCE_LENGTH],
+    pub velocity: [u8; SEQUENCE_LENGTH],
+    pub length: [u8; SEQUENCE_LENGTH],
+    pub density: [u8; SEQUENCE_LENGTH],
+}
+
+impl Track {
+    /// Creates a track with every step set to 0
+    pub fn new() -> Track {
+        Track {
+            pitch: [0; SEQUENCE_LENGTH],
+            velocity: [0; SEQUENCE_LENGTH],
+            length: [0; SEQUENCE_LENGTH],
+            density: [0; SEQUENCE_LENGTH],
+        }
+    }
+}
+
+impl Default for Track {
+    fn default() -> Track {
+        Track::new()
+    }
+}
+
+/// Virtual machine state a NSL script runs against
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub memory: [u8; MEMORY_SIZE],
+    pub params: [u8; PARAMS_COUNT],
+    /// Step sequences of every track. There is always at least one track
+    pub tracks: Vec<Track>,
+    /// Index of the track step sources refer to, set by SelectTrack
+    pub active_track: u8,
+    /// Scale used by QuantizePitch (chromatic by default)
+    pub scale: Scale,
+    /// Set when the script ran a command that only the device can execute (it was skipped)
+    pub device_only: bool,
+    /// How arithmetic results outside 0-127 are handled
+    pub overflow: OverflowMode,
+    /// Result of the last conditional the script evaluated. When false, `run` skips to its CondEnd
+    pub cond_result: bool,
+    /// State of the generator used by the random sources
+    rng: u32,
+}
+
+impl Machine {
+    /// Creates a machine with `TRACK_COUNT` tracks whose active (first) track starts with the given
+    /// step sequences, so step sources read realistic values. Everything else is set to 0.
+    pub fn new(
+        pitch: [u8; SEQUENCE_LENGTH],
+        velocity: [u8; SEQUENCE_LENGTH],
+        length: [u8; SEQUENCE_LENGTH],
+        density: [u8; SEQUENCE_LENGTH],
+    ) -> Machine {
+        let mut machine = Machine::with_tracks(TRACK_COUNT);
+        machine.tracks[0] = Track { pitch, velocity, length, density };
+        machine
+    }
+
+    /// Creates a machine with the given number of tracks (at least one)
+    pub fn with_tracks(count: usize) -> Machine {
+        Machine {
+            memory: [0; MEMORY_SIZE],
+            params: [0; PARAMS_COUNT],
+            tracks: vec![Track::new(); count.max(1)],
+            active_track: 0,
+            scale: Scale::chromatic(),
+            device_only: false,
+            overflow: OverflowMode::Saturate,
+            cond_result: false,
+            rng: 0x2545_F491,
+        }
+    }
+
+    /// Returns the active track
+    pub fn track(&self) -> &Track {
+        &self.tracks[self.active_index()]
+    }
+
+    /// Returns the active track for writing
+    pub fn track_mut(&mut self) -> &mut Track {
+        let index = self.active_index();
+        &mut self.tracks[index]
+    }
+
+    /// Index of the active track, clamped to the tracks of the machine
+    fn active_index(&self) -> usize {
+        (self.active_track as usize).min(self.tracks.len() - 1)
+    }
+
+    /// Seeds the generator used by `random` and `random_note` sources
+    pub fn seed(&mut self, seed: u32) {
+        // xorshift gets stuck on 0
+        self.rng = seed.max(1);
+    }
+
+    /// Returns a random value between 0 and max (inclusive)
+    fn random(&mut self, max: u8) -> u8 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng % (max as u32 + 1)) as u8
+    }
+
+    /// Resolves a DataValue into a number, reading the memory buffer for buffer references
+    fn value(&self, value: &DataValue) -> u8 {
+        match value {
+            DataValue::Number(x) => *x,
+            DataValue::Buffer(x) => self.memory[(*x as usize).min(MEMORY_SIZE - 1)]
+        }
+    }
+
+    /// Reads the current value of a DataSource.
+    /// Scale and FullScale sources read back their index and ignore `Machine::scale`: how NGEN
+    /// maps a scale index to a note isn't part of the format, and `scale` is only the target of QuantizePitch.
+    pub fn read(&mut self, source: &DataSource) -> u8 {
+        match source {
+            DataSource::Constant(x) => self.value(x),
+            DataSource::Random(x) => {
+                let max = self.value(x);
+                self.random(max)
+            },
+            DataSource::RandomNote(x) => {
+                let max = self.value(x);
+                self.random(max)
+            },
+            DataSource::StepPitch(x) => self.track().pitch[step(self.value(x))],
+            DataSource::StepVelocity(x) => self.track().velocity[step(self.value(x))],
+            DataSource::StepLength(x) => self.track().length[step(self.value(x))],
+            DataSource::StepDensity(x) => self.track().density[step(self.value(x))],
+            DataSource::MemoryBuffer(x) => self.memory[(self.value(x) as usize).min(MEMORY_SIZE - 1)],
+            DataSource::Params(x) => self.params[(self.value(x) as usize).min(PARAMS_COUNT - 1)],
+            DataSource::Scale(x) => self.value(x),
+            DataSource::FullScale(x) => self.value(x),
+        }
+    }
+
+    /// Resolves both operands of a conditional and compares them as unsigned numbers, the way the device does.
+    /// Returns None for commands that aren't conditionals.
+    pub fn evaluate(&mut self, command: &Commands) -> Option<bool> {
+        let (x, y) = match command {
+            Commands::CondE(x, y)
+            | Commands::CondNE(x, y)
+            | Commands::CondGT(x, y)
+            | Commands::CondLT(x, y)
+            | Commands::CondGTE(x, y)
+            | Commands::CondLTE(x, y) => (self.read(x), self.read(y)),
+            _ => return None
+        };
+        Some(match command {
+            Commands::CondE(_, _) => x == y,
+            Commands::CondNE(_, _) => x != y,
+            Commands::CondGT(_, _) => x > y,
+            Commands::CondLT(_, _) => x < y,
+            Commands::CondGTE(_, _) => x >= y,
+            _ => x <= y
+        })
+    }
+
+    /// Writes a value to a DataSource, returning false if the source is read-only
+    pub fn write(&mut self, source: &DataSource, value: u8) -> bool {
+        let value = value.min(VALUE_MAX);
+        let slot = match source {
+            DataSource::StepPitch(x) => {
+                let index = step(self.value(x));
+                &mut self.track_mut().pitch[index]
+            },
+            DataSource::StepVelocity(x) => {
+                let index = step(self.value(x));
+                &mut self.track_mut().velocity[index]
+            },
+            DataSource::StepLength(x) => {
+                let index = step(self.value(x));
+                &mut self.track_mut().length[index]
+            },
+            DataSource::StepDensity(x) => {
+                let index = step(self.value(x));
+                &mut self.track_mut().density[index]
+            },
+            DataSource::MemoryBuffer(x) => {
+                let index = (self.value(x) as usize).min(MEMORY_SIZE - 1);
+                &mut self.memory[index]
+            },
+            DataSource::Params(x) => {
+                let index = (self.value(x) as usize).min(PARAMS_COUNT - 1);
+                &mut self.params[index]
+            },
+            _ => return false
+        };
+        *slot = value;
+        true
+    }
+
+    /// Runs the script until it ends, stopping with an error after `max_steps` commands
+    pub fn run(&mut self, script: &NSLScript, max_steps: usize) -> Result<(), VmError> {
+        let commands = &script.commands;
+        let offsets: Vec<usize> = script.iter_with_offsets().map(|(offset, _)| offset).collect();
+
+        // Open loops as (index of the first command of the body, remaining repetitions)
+        let mut loops: Vec<(usize, u8)> = Vec::new();
+        let mut index = 0;
+        let mut steps = 0;
+        while index < commands.len() {
+            if steps == max_steps {
+                return Err(VmError::StepLimit { max_steps });
+            }
+            steps += 1;
+
+            let command = &commands[index];
+            debug!("VM {}: {}", index, command);
+            match command {
+                Commands::Set(x, y) | Commands::Copy(x, y) => {
+                    let value = self.read(y);
+                    self.store(index, x, value)?;
+                },
+                Commands::Add(x, y) => {
+                    let result = self.read(x) as i32 + self.read(y) as i32;
+                    let value = self.arithmetic_result(index, result)?;
+                    self.store(index, x, value)?;
+                },
+                Commands::Subtract(x, y) => {
+                    let result = self.read(x) as i32 - self.read(y) as i32;
+                    let value = self.arithmetic_result(index, result)?;
+                    self.store(index, x, value)?;
+                },
+                Commands::Multiply(x, y) => {
+                    let result = self.read(x) as i32 * self.read(y) as i32;
+                    let value = self.arithmetic_result(index, result)?;
+                    self.store(index, x, value)?;
+                },
+                Commands::Divide(x, y) => {
+                    let divisor = self.read(y);
+                    if divisor == 0 {
+                        return Err(VmError::DivideByZero { index });
+                    }
+                    let value = self.read(x) / divisor;
+                    self.store(index, x, value)?;
+                },
+                Commands::LoopSet(x) => {
+                    let count = self.read(x);
+                    loops.push((index + 1, count));
+                },
+                Commands::LoopEnd => {
+                    match loops.last_mut() {
+                        // The body always runs at least once
+                        Some((start, remaining)) if *remaining > 1 => {
+                            *remaining -= 1;
+                            index = *start;
+                            continue;
+                        },
+                        Some(_) => {
+                            loops.pop();
+                        },
+                        None => return Err(VmError::UnmatchedLoopEnd { index })
+                    }
+                },
+                Commands::Jump(x) => {
+                    let target = x.get_value();
+                    match offsets.iter().position(|offset| *offset == target as usize) {
+                        Some(next) => {
+                            index = next;
+                            continue;
+                        },
+                        None => return Err(VmError::BadJump { index, target })
+                    }
+                },
+                // Unresolved pseudo-commands encode as a jump to 0x0000
+                Commands::JumpLabel(_) | Commands::JumpRel(_) => return Err(VmError::BadJump { index, target: 0 }),
+                Commands::CondE(_, _)
+                | Commands::CondNE(_, _)
+                | Commands::CondGT(_, _)
+                | Commands::CondLT(_, _)
+                | Commands::CondGTE(_, _)
+                | Commands::CondLTE(_, _) => {
+                    self.cond_result = self.evaluate(command).unwrap_or(false);
+                    if !self.cond_result {
+                        index = skip_conditional(commands, index);
+                        continue;
+                    }
+                },
+                Commands::ClearTrack => *self.track_mut() = Track::new(),
+                Commands::ClearMemory => self.memory = [0; MEMORY_SIZE],
+                Commands::ClearAll => {
+                    self.tracks.iter_mut().for_each(|track| *track = Track::new());
+                    self.memory = [0; MEMORY_SIZE];
+                },
+                Commands::SelectTrack(x) => {
+                    let track = self.read(x) as usize;
+                    self.active_track = track.min(self.tracks.len() - 1) as u8;
+                },
+                Commands::ClearTrackAt(x) => {
+                    let track = (self.read(x) as usize).min(self.tracks.len() - 1);
+                    self.tracks[track] = Track::new();
+                },
+                Commands::QuantizePitch => {
+                    let scale = self.scale;
+                    let track = self.track_mut();
+                    track.pitch = track.pitch.map(|pitch| scale.quantize(pitch));
+                },
+                Commands::GenerateProgression
+                | Commands::GenerateEuclidean(_, _) => {
+                    debug!("Skipping device only command {}", command);
+                    self.device_only = true;
+                },
+                Commands::End => return Ok(()),
+                Commands::None | Commands::CondEnd | Commands::Label(_) => {}
+            }
+            index += 1;
+        }
+        Ok(())
+    }
+
+    /// Applies the overflow mode to the result of the arithmetic command at `index`
+    fn arithmetic_result(&self, index: usize, result: i32) -> Result<u8, VmError> {
+        self.overflow.apply(result).ok_or(VmError::Overflow { index })
+    }
+
+    /// Writes the result of the command at `index` to its destination
+    fn store(&mut self, index: usize, destination: &DataSource, value: u8) -> Result<(), VmError> {
+        if self.write(destination, value) {
+            Ok(())
+        } else {
+            Err(VmError::NotWritable { index })
+        }
+    }
+}
+
+impl Default for Machine {
+    /// A machine with `TRACK_COUNT` tracks and all memory, params and steps set to 0
+    fn default() -> Machine {
+        Machine::with_tracks(TRACK_COUNT)
+    }
+}
+
+/// Clamps a step index to the sequence length
+fn step(index: u8) -> usize {
+    (index as usize).min(SEQUENCE_LENGTH - 1)
+}
+
+/// Returns the index of the command after the CondEnd closing the conditional at `index`
+fn skip_conditional(commands: &[Commands], index: usize) -> usize {
+    let mut depth = 0;
+    for (i, command) in commands.iter().enumerate().skip(index) {
+        match command {
+            Commands::CondE(_, _)
+            | Commands::CondNE(_, _)
+            | Commands::CondGT(_, _)
+            | Commands::CondLT(_, _)
+            | Commands::CondGTE(_, _)
+            | Commands::CondLTE(_, _) => depth += 1,
+            Commands::CondEnd => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            },
+            _ => {}
+        }
+    }
+    commands.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn arithmetic_and_loops() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(memory_buffer(0), constant(0)),
+            Commands::LoopSet(constant(4)),
+            Commands::Add(memory_buffer(0), constant(3)),
+            Commands::LoopEnd,
+            Commands::Copy(memory_buffer(1), memory_buffer(0)),
+            Commands::Multiply(memory_buffer(1), constant(20)),
+            Commands::Set(step_pitch(2), memory_buffer(0)),
+            Commands::Subtract(step_pitch(2), constant(2)),
+            Commands::End,
+            Commands::Set(memory_buffer(2), constant(1)),
+        ]);
+        let mut machine = Machine::default();
+        machine.run(&script, 100).unwrap();
+        assert_eq!(machine.memory[0], 12);
+        assert_eq!(machine.memory[1], 127);
+        assert_eq!(machine.track().pitch[2], 10);
+        assert_eq!(machine.memory[2], 0);
+    }
+
+    #[test]
+    fn overflow_modes() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(memory_buffer(0), constant(100)),
+            Commands::Add(memory_buffer(0), constant(50)),
+            Commands::Set(memory_buffer(1), constant(3)),
+            Commands::Subtract(memory_buffer(1), constant(5)),
+        ]);
+        let mut machine = Machine::default();
+        assert_eq!(machine.overflow, OverflowMode::Saturate);
+        machine.run(&script, 100).unwrap();
+        assert_eq!(machine.memory[..2], [127, 0]);
+
+        let mut machine = Machine { overflow: OverflowMode::Wrap, ..Machine::default() };
+        machine.run(&script, 100).unwrap();
+        assert_eq!(machine.memory[..2], [22, 126]);
+
+        let mut machine = Machine { overflow: OverflowMode::Error, ..Machine::default() };
+        assert_eq!(machine.run(&script, 100), Err(VmError::Overflow { index: 1 }));
+    }
+
+    #[test]
+    fn active_track() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(step_pitch(0), constant(36)),
+            Commands::SelectTrack(constant(2)),
+            Commands::Set(step_pitch(0), constant(48)),
+            Commands::Copy(memory_buffer(0), step_pitch(0)),
+            Commands::ClearTrack,
+            // Clamped to the last track
+            Commands::SelectTrack(constant(9)),
+            Commands::Set(step_pitch(0), constant(60)),
+        ]);
+        let mut machine = Machine::default();
+        machine.run(&script, 100).unwrap();
+        assert_eq!(machine.memory[0], 48);
+        assert_eq!(machine.tracks[0].pitch[0], 36);
+        assert_eq!(machine.tracks[2].pitch[0], 0);
+        assert_eq!(machine.active_track, TRACK_COUNT as u8 - 1);
+        assert_eq!(machine.track().pitch[0], 60);
+        assert!(!machine.device_only);
+
+        let mut machine = Machine::with_tracks(1);
+        machine.run(&script, 100).unwrap();
+        assert_eq!(machine.active_track, 0);
+        assert_eq!(machine.tracks[0].pitch[0], 60);
+    }
+
+    #[test]
+    fn clear_track_at() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(step_pitch(0), constant(36)),
+            Commands::SelectTrack(constant(1)),
+            Commands::Set(step_pitch(0), constant(48)),
+            Commands::ClearTrackAt(constant(0)),
+        ]);
+        let mut machine = Machine::default();
+        machine.run(&script, 100).unwrap();
+        assert_eq!(machine.tracks[0].pitch[0], 0);
+        assert_eq!(machine.tracks[1].pitch[0], 48);
+        assert_eq!(machine.active_track, 1);
+    }
+
+    #[test]
+    fn quantize_pitch() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Set(step_pitch(0), constant(61)),
+            Commands::Set(step_pitch(1), constant(66)),
+            Commands::QuantizePitch,
+        ]);
+        let mut machine = Machine { scale: Scale::major(), ..Machine::default() };
+        machine.run(&script, 100).unwrap();
+        assert_eq!(machine.track().pitch[..2], [60, 65]);
+        assert!(!machine.device_only);
+    }
+
+    #[test]
+    fn conditionals() {
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::CondGT(params(0), constant(64)),
+            Commands::Set(memory_buffer(0), constant(1)),
+            Commands::CondE(memory_buffer(1), constant(0)),
+            Commands::Set(memory_buffer(2), constant(1)),
+            Commands::CondEnd,
+            Commands::CondEnd,
+            Commands::Set(memory_buffer(3), constant(1)),
+        ]);
+        let mut machine = Machine::default();
+        machine.run(&script, 100).unwrap();
+        assert_eq!(machine.memory[..4], [0, 0, 0, 1]);
+
+        let mut machine = Machine::default();
+        machine.params[0] = 100;
+        machine.run(&script, 100).unwrap();
+        assert_eq!(machine.memory[..4], [1, 0, 1, 1]);
+    }
+
+    #[test]
+    fn comparisons() {
+        let mut machine = Machine::default();
+        machine.memory[0] = 5;
+        machine.memory[1] = 9;
+        machine.tracks[0].pitch[9] = 5;
+        let five = DataSource::Constant(DataValue::Buffer(0));
+        let pitch = DataSource::StepPitch(DataValue::Buffer(1));
+        // (x, y, [E, NE, GT, LT, GTE, LTE])
+        let cases = [
+            (constant(5), constant(5), [true, false, false, false, true, true]),
+            (constant(5), constant(9), [false, true, false, true, false, true]),
+            (constant(9), constant(5), [false, true, true, false, true, false]),
+            (memory_buffer(0), five.clone(), [true, false, false, false, true, true]),
+            (memory_buffer(1), five.clone(), [false, true, true, false, true, false]),
+            (pitch.clone(), memory_buffer(1), [false, true, false, true, false, true]),
+            (pitch, five, [true, false, false, false, true, true]),
+        ];
+        for (x, y, expected) in cases {
+            let conditionals = [
+                Commands::CondE(x.clone(), y.clone()),
+                Commands::CondNE(x.clone(), y.clone()),
+                Commands::CondGT(x.clone(), y.clone()),
+                Commands::CondLT(x.clone(), y.clone()),
+                Commands::CondGTE(x.clone(), y.clone()),
+                Commands::CondLTE(x.clone(), y.clone()),
+            ];
+            for (command, expected) in conditionals.iter().zip(expected) {
+                assert_eq!(machine.evaluate(command), Some(expected), "{}", command);
+            }
+        }
+        assert_eq!(machine.evaluate(&Commands::CondEnd), None);
+
+        let mut script = NSLScript::new();
+        script.add_commands(vec![Commands::CondLT(memory_buffer(0), constant(3)), Commands::CondEnd, Commands::End]);
+        machine.run(&script, 10).unwrap();
+        assert!(!machine.cond_result);
+        machine.memory[0] = 2;
+        machine.run(&script, 10).unwrap();
+        assert!(machine.cond_result);
+    }
+
+    #[test]
+    fn fixture_script() {
+        let script = NSLScript::try_from_u8_vec(FIXTURE).unwrap();
+        let mut machine = Machine::default();
+        machine.params[1] = 99;
+        machine.run(&script, 1000).unwrap();
+        // The loop walks mem[0] over all 32 steps
+        assert_eq!(machine.memory[0], 32);
+        assert_eq!(machine.memory[1], 33);
+        // GenerateEuclidean only runs on the device
+        assert!(machine.device_only);
+    }
+
+    #[test]
+    fn seeded_sequences() {
+        let pitch: [u8; SEQUENCE_LENGTH] = core::array::from_fn(|step| 36 + step as u8);
+        let mut machine = Machine::new(pitch, [100; SEQUENCE_LENGTH], [0; SEQUENCE_LENGTH], [0; SEQUENCE_LENGTH]);
+        let mut script = NSLScript::new();
+        script.add_commands(vec![
+            Commands::Copy(memory_buffer(0), step_pitch(3)),
+            Commands::Copy(memory_buffer(1), step_velocity(3)),
+            Commands::End,
+        ]);
+        machine.run(&script, 10).unwrap();
+        assert_eq!(machine.memory[0], 39);
+        assert_eq!(machine.memory[1], 100);
+        assert_eq!(machine.tracks[1], Track::new());
+    }
+
+    #[test]
+    fn errors() {
+        let mut script = NSLScript::new();
+        script.add_label("start");
+        script.add_command(Commands::JumpLabel("start".to_string()));
+        script.resolve_labels().unwrap();
+        assert_eq!(Machine::default().run(&script, 50), Err(VmError::StepLimit { max_steps: 50 }));
+
+        let mut script = NSLScript::new();
+        script.add_command(Commands::Set(constant(1), constant(2)));
+        assert_eq!(Machine::default().run(&script, 50), Err(VmError::NotWritable { index: 0 }));
+
+        let mut script = NSLScript::new();
+        script.add_command(Commands::Divide(memory_buffer(0), memory_buffer(1)));
+        assert_eq!(Machine::default().run(&script, 50), Err(VmError::DivideByZero { index: 0 }));
+
+        let mut script = NSLScript::new();
+        script.add_command(Commands::Jump(Int16::new(0, 5)));
+        assert_eq!(Machine::default().run(&script, 50), Err(VmError::BadJump { index: 0, target: 5 }));
+    }
+}